@@ -0,0 +1,110 @@
+use clap::ValueEnum;
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// When the output should be colorized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Colorize only if stdout is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Yellow,
+    Green,
+    Red,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Yellow => "33",
+            Color::Green => "32",
+            Color::Red => "31",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide if the output has to be colorized
+///
+/// `always` and `never` are forced by the user,
+/// `auto` colorizes a terminal, unless NO_COLOR is set
+fn should_colorize(choice: ColorChoice, no_color: bool, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => !no_color && is_terminal,
+    }
+}
+
+/// Set the color policy for the whole program
+pub fn init(choice: ColorChoice) {
+    // https://no-color.org: NO_COLOR is ignored when empty
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let is_terminal = std::io::stdout().is_terminal();
+    ENABLED.store(
+        should_colorize(choice, no_color, is_terminal),
+        Ordering::Relaxed,
+    );
+}
+
+/// Returns true if the output is colorized
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn paint_with(enabled: bool, color: Color, text: &str) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Paint the text with the given color, if the output is colorized
+pub fn paint(color: Color, text: &str) -> String {
+    paint_with(enabled(), color, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{paint_with, should_colorize, Color, ColorChoice};
+
+    #[test]
+    fn test_never_suppresses_colors() {
+        let enabled = should_colorize(ColorChoice::Never, false, true);
+        assert!(!enabled);
+        assert!(!paint_with(enabled, Color::Green, "main").contains('\x1b'));
+    }
+
+    #[test]
+    fn test_no_color_suppresses_colors() {
+        let enabled = should_colorize(ColorChoice::Auto, true, true);
+        assert!(!enabled);
+        assert!(!paint_with(enabled, Color::Green, "main").contains('\x1b'));
+    }
+
+    #[test]
+    fn test_auto_colorizes_a_terminal_only() {
+        assert!(should_colorize(ColorChoice::Auto, false, true));
+        assert!(!should_colorize(ColorChoice::Auto, false, false));
+    }
+
+    #[test]
+    fn test_always_forces_colors() {
+        let enabled = should_colorize(ColorChoice::Always, true, false);
+        assert!(enabled);
+        assert_eq!(
+            paint_with(enabled, Color::Green, "main"),
+            "\x1b[32mmain\x1b[0m"
+        );
+    }
+}
@@ -1,45 +1,87 @@
 use crate::{
-    core::{apply, save_note},
-    git::Git,
-    parser::{commits_to_string, instruction_from_string},
+    core::{apply, refuse_if_in_progress, warn_if_shallow},
+    git::{BranchName, Git, Origin},
 };
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Args;
 
+/// Move the local branches to the commits of their notes,
+/// without opening the editor, writing the notes or pushing
 #[derive(Debug, Args)]
-pub struct Apply {}
-
-const COMMENTS: &str = r#"
-# Here is how to use yggit
-# 
-# Commands:
-# -> <branch> add a branch to the above commit
-# -> <origin>:<branch> add a branch to the above commit
-# 
-# What happens next?
-#  - All branches are pushed on origin, except if you specified a custom origin
-#
-# It's not a rebase, you can't edit commits nor reorder them
-"#;
+pub struct Apply {
+    /// Print a diff stat between the previous and the new tip of each moved branch
+    #[arg(long)]
+    show_diff: bool,
+    /// only update this branch, the others are left untouched,
+    /// can be repeated
+    #[arg(long)]
+    only: Vec<BranchName>,
+    /// track this origin for the branches without an origin in their notes,
+    /// instead of yggit.defaultUpstream
+    #[arg(long)]
+    origin: Option<Origin>,
+}
 
 impl Apply {
-    pub fn execute(&self, git: Git) -> Result<()> {
-        let commits = git.list_commits()?;
-        let output = commits_to_string(commits);
-
-        let file_path = "/tmp/yggit";
+    pub fn execute(&self, mut git: Git) -> Result<()> {
+        git.set_default_upstream(self.origin.as_ref());
+        warn_if_shallow(&git);
+        refuse_if_in_progress(&git)?;
 
-        let output = format!("{}\n{}", output, COMMENTS);
-        std::fs::write(file_path, output).context("Cannot write yggit file to filesystem")?;
+        apply(&git, self.show_diff, &self.only)?;
 
-        let content = git.edit_file(file_path)?;
+        Ok(())
+    }
+}
 
-        let commits = instruction_from_string(content).context("Cannot parse instructions")?;
+#[cfg(test)]
+mod tests {
+    use super::Apply;
+    use crate::{
+        core::{Note, Push},
+        git::{
+            tmp::{git, git_config, GitTmp},
+            Git,
+        },
+    };
 
-        save_note(&git, commits)?;
+    #[test]
+    fn test_apply_without_editor() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+        repo.add_note(
+            head,
+            &Note {
+                pushes: vec![Push {
+                    origin: None,
+                    branch: "feature".into(),
+                    strategy: None,
+                }],
+                ..Default::default()
+            },
+        );
+        // Opening the editor would fail
+        git_config!(repo, "yggit.ui", "none");
 
-        apply(&git)?;
+        let apply = Apply {
+            show_diff: false,
+            only: Vec::default(),
+            origin: None,
+        };
+        apply.execute(Git::open(&repo.path()).unwrap()).unwrap();
 
-        Ok(())
+        let branch = git!(repo, "rev-parse", "feature");
+        assert_eq!(branch.trim(), head.to_string());
+        let notes = git!(repo, "notes", "list");
+        assert_eq!(notes.lines().count(), 1);
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(!remote.contains("refs/heads/feature"));
     }
 }
@@ -0,0 +1,20 @@
+use crate::{
+    core::{clean_branches, refuse_if_in_progress},
+    git::Git,
+};
+use anyhow::Result;
+use clap::Args;
+
+/// Remove the branches from the notes of the stack, the tags and the other keys are kept
+#[derive(Debug, Args)]
+pub struct Clean {}
+
+impl Clean {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        refuse_if_in_progress(&git)?;
+
+        let removed = clean_branches(&git)?;
+        println!("{} branch notes removed", removed);
+        Ok(())
+    }
+}
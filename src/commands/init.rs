@@ -0,0 +1,27 @@
+use crate::git::Git;
+use anyhow::Result;
+use clap::Args;
+
+/// Set up the git configuration required by yggit
+#[derive(Debug, Args)]
+pub struct Init {
+    /// upstream used when a branch has no origin
+    #[arg(long)]
+    default_upstream: Option<String>,
+    /// overwrite the values that are already set
+    #[arg(long, default_value_t = false)]
+    force: bool,
+}
+
+impl Init {
+    pub fn execute(&self) -> Result<()> {
+        let changes = Git::init(".", self.default_upstream.as_deref(), self.force)?;
+        if changes.is_empty() {
+            println!("already initialized");
+        }
+        for change in changes {
+            println!("set {}", change);
+        }
+        Ok(())
+    }
+}
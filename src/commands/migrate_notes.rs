@@ -0,0 +1,35 @@
+use crate::{
+    core::{migrate_note, refuse_if_in_progress},
+    git::Git,
+};
+use anyhow::Result;
+use clap::Args;
+
+/// Move the note of a commit to another one, for instance when the commit has been amended
+/// without notes.rewriteRef
+#[derive(Debug, Args)]
+pub struct MigrateNotes {
+    /// commit having the note
+    old: String,
+    /// commit receiving the note
+    new: String,
+    /// replace the note of the new commit if it already has one
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+}
+
+impl MigrateNotes {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        refuse_if_in_progress(&git)?;
+
+        let old = git.resolve_commit(&self.old)?;
+        let new = git.resolve_commit(&self.new)?;
+        migrate_note(&git, old, new, self.force)?;
+        println!(
+            "note moved from {} to {}",
+            &old.to_string()[..7],
+            &new.to_string()[..7]
+        );
+        Ok(())
+    }
+}
@@ -1,3 +1,10 @@
+pub mod apply;
+pub mod clean;
+pub mod init;
+pub mod migrate_notes;
 pub mod push;
+pub mod rebase;
+pub mod reset;
 pub mod show;
-pub mod apply;
\ No newline at end of file
+pub mod status;
+pub mod test;
@@ -1,10 +1,15 @@
 use crate::{
-    core::{apply, push_from_notes, save_note},
-    git::Git,
-    parser::{commits_to_string, instruction_from_string},
+    core::{
+        apply, confirm, delete_branches, edit_todo, push_from_notes, push_plan,
+        refuse_if_in_progress, reorder, reordered, restrict_to_branches, resume_push, save_note,
+        warn_if_shallow, Note, Progress, Verbosity, SCISSORS,
+    },
+    git::{BranchName, EnhancedCommit, Git, Origin},
+    parser::commits_to_string,
 };
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Args;
+use std::io::IsTerminal;
 
 #[derive(Debug, Args)]
 pub struct Push {
@@ -12,39 +17,399 @@ pub struct Push {
     /// by default it is using --force-with-lease
     #[arg(short, long, default_value_t = false)]
     force: bool,
+    /// print the content that would be opened in the editor and exit,
+    /// nothing is modified
+    #[arg(long, default_value_t = false)]
+    print_todo: bool,
+    /// number of times a push failing because of the network is retried
+    #[arg(long, default_value_t = 2)]
+    max_retries: u32,
+    /// only update and push this branch, the others are left untouched,
+    /// can be repeated
+    #[arg(long)]
+    only: Vec<BranchName>,
+    /// fetch the origins before pushing, so that the lease is checked against their latest state
+    #[arg(long, default_value_t = false)]
+    fetch: bool,
+    /// print the branches that would be moved and pushed after editing the todo,
+    /// neither the notes, the branches nor the remote are modified
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// push the branches without an origin in the todo to this origin,
+    /// instead of yggit.defaultUpstream
+    #[arg(long)]
+    origin: Option<Origin>,
+    /// only print the branches that have been created, moved or not pushed
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+    /// print every branch, with its previous and new commits
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+    /// push the branches of each origin in a single atomic push,
+    /// either all of them are updated or none is.
+    /// It runs `git push --atomic`, given GIT_TOKEN, GITHUB_TOKEN and YGGIT_SSH_KEY,
+    /// without them git authenticates with its own credential helpers and ssh keys
+    #[arg(long, default_value_t = false)]
+    atomic: bool,
+}
+
+impl Push {
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
 }
 
 const COMMENTS: &str = r#"
+# Do not modify or remove the line above, everything below it is ignored
+#
 # Here is how to use yggit
 # 
 # Commands:
 # -> <branch> add a branch to the above commit
 # -> <origin>:<branch> add a branch to the above commit
+# -> <branch> (force|lease|normal) choose how the branch is pushed, lease by default
+# -> tag:<name> add a lightweight tag to the above commit
+# -> tag:<name> "<message>" add an annotated tag to the above commit
+# -> !<branch> delete the branch from origin instead of pushing it
+# $ <command> or exec <command> test the above commit with the command, run by yggit test
+# # <comment> is kept with the commit below it
 # 
 # What happens next?
 #  - All branches are pushed on origin, except if you specified a custom origin
 #
-# It's not a rebase, you can't edit commits nor reorder them
+# The commits can be reordered by moving their lines, editing their titles has no effect
 "#;
 
+/// Build the content handed to the editor
+fn todo(commits: Vec<EnhancedCommit<Note>>) -> String {
+    let output = commits_to_string(commits);
+    format!("{}\n{}{}", output, SCISSORS, COMMENTS)
+}
+
 impl Push {
-    pub fn execute(&self, git: Git) -> Result<()> {
+    pub fn execute(&self, mut git: Git) -> Result<()> {
+        git.set_default_upstream(self.origin.as_ref());
+        warn_if_shallow(&git);
+
         let commits = git.list_commits()?;
-        let output = commits_to_string(commits);
+        let output = todo(commits);
 
-        let file_path = "/tmp/yggit";
+        if self.print_todo {
+            print!("{}", output);
+            return Ok(());
+        }
 
-        let output = format!("{}\n{}", output, COMMENTS);
-        std::fs::write(file_path, output).context("cannot write file to disk")?;
+        refuse_if_in_progress(&git)?;
 
-        let content = git.edit_file(file_path)?;
+        if let Some(progress) = Progress::load(git.git_dir())? {
+            // The answer would be taken from the todo piped into stdin, it is never guessed
+            if git.editor_reads_stdin() || !std::io::stdin().is_terminal() {
+                return Err(anyhow::Error::msg(
+                    "a previous push has been interrupted, resuming it needs an answer that cannot be read from stdin\n\
+                     hint: run yggit push in a terminal, without --from-stdin, to resume or discard it",
+                ));
+            }
+            if confirm("A previous push has been interrupted, resume it?")? {
+                let report = resume_push(&git, progress, self.max_retries)?;
+                print!("{}", report.render(self.verbosity()));
+                return report.check();
+            }
+            Progress::clear(git.git_dir())?;
+        }
 
-        let commits = instruction_from_string(content).context("Cannot parse instruction")?;
+        let commits = edit_todo(&git, &output)?;
+        let order = reordered(&git.list_commits()?, &commits);
 
+        if self.dry_run {
+            let plan = push_plan(&git, &commits, &self.only, self.force)?;
+            if order.is_some() {
+                println!("would reorder the commits");
+            } else if plan.is_empty() {
+                println!("nothing would be pushed");
+            }
+            for line in plan {
+                println!("{line}");
+            }
+            return Ok(());
+        }
+
+        let commits = restrict_to_branches(&git, commits, &self.only)?;
+        let deleted = delete_branches(&git, &commits, &self.only)?;
         save_note(&git, commits)?;
-        apply(&git)?;
-        push_from_notes(&git, self.force)?;
+        if let Some(order) = order {
+            reorder(&git, &order)?;
+        }
+        apply(&git, false, &self.only)?;
+        let mut report = push_from_notes(
+            &git,
+            self.force,
+            self.max_retries,
+            self.only.clone(),
+            self.fetch,
+            self.origin.clone(),
+            self.atomic,
+        )?;
+        report.pushed.splice(0..0, deleted);
+        print!("{}", report.render(self.verbosity()));
+        report.check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{todo, Push as PushCommand, COMMENTS};
+    use crate::{
+        core::{Note, Progress, Push, RECOVER_FILE, SCISSORS},
+        editor,
+        git::{
+            tmp::{git, git_config, GitTmp},
+            EnhancedCommit, Git, Ui,
+        },
+    };
+    use git2::Oid;
+
+    #[test]
+    fn test_todo_contains_commits_and_comments() {
+        let commits = vec![EnhancedCommit {
+            id: Oid::zero(),
+            parents: Vec::default(),
+            title: "first commit".to_string(),
+            description: None,
+            note: Some(Note {
+                pushes: vec![Push {
+                    origin: None,
+                    branch: "feature".into(),
+                    strategy: None,
+                }],
+                ..Default::default()
+            }),
+        }];
+        let output = todo(commits);
+        let expected = format!(
+            "{} first commit\n-> feature\n\n\n{}{}",
+            Oid::zero(),
+            SCISSORS,
+            COMMENTS
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_push_without_editor() {
+        let repo = GitTmp::init_bare("main");
+        let clone = repo.clone();
+        clone.new_file("readme.md", "Hello there\n");
+        clone.add_all();
+        clone.commit("first commit");
+        clone.create_branch("feature");
+        clone.new_file("feature.md", "General Kenobi\n");
+        clone.add_all();
+        clone.commit("feature commit");
+        git_config!(clone, "yggit.ui", "none");
+
+        let git = Git::open(&clone.path()).unwrap();
+        let push = PushCommand {
+            force: false,
+            print_todo: false,
+            max_retries: 0,
+            only: Vec::default(),
+            fetch: false,
+            dry_run: false,
+            origin: None,
+            quiet: false,
+            verbose: false,
+            atomic: false,
+        };
+        let Err(error) = push.execute(git) else {
+            panic!("push needs an editor")
+        };
+        assert!(error.to_string().contains("yggit.ui is none"));
+    }
+
+    #[test]
+    fn test_interrupted_push_with_todo_from_stdin() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_editor(editor::from_config(Ui::Stdin, None));
+        let progress = git.git_dir().join("yggit-progress");
+        std::fs::write(
+            &progress,
+            serde_json::to_string(&Progress::default()).unwrap(),
+        )
+        .unwrap();
+
+        // The first line of the todo is not taken as the answer
+        let push = PushCommand {
+            force: false,
+            print_todo: false,
+            max_retries: 0,
+            only: Vec::default(),
+            fetch: false,
+            dry_run: false,
+            origin: None,
+            quiet: false,
+            verbose: false,
+            atomic: false,
+        };
+        let Err(error) = push.execute(git) else {
+            panic!("the interrupted push needs an answer")
+        };
+        assert!(error
+            .to_string()
+            .contains("hint: run yggit push in a terminal"));
+        assert!(progress.exists());
+    }
+
+    #[test]
+    fn test_push_end_to_end() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+
+        repo.run_push(
+            move |todo| {
+                let line = format!("{} feature commit\n", head);
+                todo.replace(&line, &format!("{line}-> feature\n"))
+            },
+            false,
+            None,
+        )
+        .expect("should be pushed");
+
+        git!(repo, "fetch");
+        let remote = git!(repo, "rev-parse", "origin/feature");
+        assert_eq!(remote.trim(), head.to_string());
+
+        repo.run_push(
+            move |todo| todo.replace("-> feature\n", "-> !feature\n"),
+            false,
+            None,
+        )
+        .expect("should be deleted");
+
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(!remote.contains("refs/heads/feature"));
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(git.find_note::<Note>(head).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_push_reopens_invalid_todo() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+
+        // The typo is fixed once the todo is opened again with the error
+        repo.run_push(
+            move |todo| {
+                let line = format!("{} feature commit\n", head);
+                if todo.starts_with("# yggit error: ") {
+                    assert!(todo.contains("-> feature branch\n"));
+                    assert!(todo.ends_with(COMMENTS));
+                    todo.replace("-> feature branch\n", "-> feature\n")
+                } else {
+                    todo.replace(&line, &format!("{line}-> feature branch\n"))
+                }
+            },
+            false,
+            None,
+        )
+        .expect("should be pushed once fixed");
+        let git = Git::open(&repo.path()).unwrap();
+        let note = git.find_note::<Note>(head).unwrap().unwrap();
+        assert_eq!(note.pushes[0].branch.as_str(), "feature");
+        assert!(note.comments.is_empty());
+
+        // The todo is kept when it is never fixed
+        let error = repo
+            .run_push(
+                move |todo| todo.replace("-> feature\n", "-> other branch\n"),
+                false,
+                None,
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains(RECOVER_FILE));
+        let recovered = std::fs::read_to_string(git.git_dir().join(RECOVER_FILE)).unwrap();
+        assert!(recovered.contains("-> other branch\n"));
+        assert!(!recovered.contains("# yggit error: "));
+    }
+
+    #[test]
+    fn test_help_is_ignored() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+
+        // The uncommented help would not be a valid instruction
+        repo.run_push(
+            move |todo| {
+                let line = format!("{} feature commit\n", head);
+                todo.replace(&line, &format!("{line}-> feature\n"))
+                    .replace("# -> <branch> add", "-> <branch> add")
+            },
+            false,
+            None,
+        )
+        .expect("should be pushed");
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/feature");
+        assert!(remote.starts_with(&head.to_string()));
+    }
+
+    #[test]
+    fn test_normal_branch_is_never_forced() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+
+        repo.run_push(
+            move |todo| {
+                let line = format!("{} feature commit\n", head);
+                todo.replace(&line, &format!("{line}-> shared (normal)\n"))
+            },
+            true,
+            None,
+        )
+        .expect("should be pushed");
 
-        Ok(())
+        // Even with --force, the shared branch is not rewound,
+        // the note follows the amended commit
+        repo.amend("amended feature commit");
+        let error = repo.run_push(|todo| todo, true, None).unwrap_err();
+        assert!(error.to_string().contains("have not been pushed"));
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/shared");
+        assert!(remote.starts_with(&head.to_string()));
     }
 }
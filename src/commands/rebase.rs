@@ -0,0 +1,25 @@
+use crate::{
+    core::{rebase, refuse_if_in_progress, warn_if_shallow},
+    git::Git,
+};
+use anyhow::Result;
+use clap::Args;
+
+/// Replay the stack on top of a new base,
+/// like `git rebase --onto <new-base> <base>`
+#[derive(Debug, Args)]
+pub struct Rebase {
+    /// branch or commit on top of which the stack is replayed
+    new_base: String,
+}
+
+impl Rebase {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        warn_if_shallow(&git);
+        refuse_if_in_progress(&git)?;
+
+        rebase(&git, &self.new_base)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use crate::{
+    core::{confirm_destructive, refuse_if_in_progress, reset_notes},
+    git::Git,
+};
+use anyhow::Result;
+use clap::Args;
+
+/// Remove the branches and the tags of every commit of the stack
+#[derive(Debug, Args)]
+pub struct Reset {
+    /// do not ask for confirmation
+    #[arg(long, short, default_value_t = false)]
+    yes: bool,
+}
+
+impl Reset {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        refuse_if_in_progress(&git)?;
+
+        if !self.yes && !confirm_destructive("Remove the branches and the tags of the stack?")? {
+            return Ok(());
+        }
+        let cleared = reset_notes(&git)?;
+        println!("{} commits cleared", cleared);
+        Ok(())
+    }
+}
@@ -1,15 +1,213 @@
-use crate::{git::Git, parser::commits_to_string};
-use anyhow::Result;
+use crate::{
+    color,
+    core::{pending_rewords, render_cached, warn_if_in_progress},
+    git::Git,
+    pager,
+    parser::{
+        commits_to_colored_string, commits_to_formatted_string, commits_to_graph, commits_to_json,
+        commits_to_string, instruction_from_string, rewords_to_string, Filter,
+    },
+};
+use anyhow::{Context, Result};
 use clap::Args;
 
 #[derive(Debug, Args)]
-pub struct Show {}
+pub struct Show {
+    /// only show the commits, without their branches
+    #[arg(long, default_value_t = false, conflicts_with = "branches_only")]
+    commits_only: bool,
+    /// only show the branches, one per line
+    #[arg(long, default_value_t = false)]
+    branches_only: bool,
+    /// show the stack as a graph, like `git log --graph --oneline`
+    #[arg(long, default_value_t = false, conflicts_with_all = ["commits_only", "branches_only"])]
+    graph: bool,
+    /// show each commit on one line following the template,
+    /// with the placeholders %h (short hash), %H (hash), %s (title), %b (branch) and %o (origin)
+    #[arg(long, conflicts_with_all = ["commits_only", "branches_only", "graph"])]
+    format: Option<String>,
+    /// show the stack as json, with the base of each commit, for tools
+    #[arg(long, default_value_t = false, conflicts_with_all = ["commits_only", "branches_only", "graph", "format"])]
+    json: bool,
+    /// open the todo in the editor and preview the commit titles that would change,
+    /// nothing is rewritten
+    #[arg(long, default_value_t = false, conflicts_with_all = ["commits_only", "branches_only", "graph", "format", "json"])]
+    pending: bool,
+    /// show the description of each commit, indented under its title
+    #[arg(long, short, default_value_t = false, conflicts_with_all = ["branches_only", "graph", "format", "json", "pending"])]
+    verbose: bool,
+    /// do not show the commands testing the commits
+    #[arg(long, default_value_t = false)]
+    no_tests: bool,
+    /// render the stack again instead of using the cached output
+    #[arg(long, default_value_t = false)]
+    no_cache: bool,
+}
 
 impl Show {
     pub fn execute(&self, git: Git) -> Result<()> {
+        warn_if_in_progress(&git);
+
+        if self.pending {
+            return self.preview_rewords(&git);
+        }
+
+        let filter = if self.commits_only {
+            Filter::CommitsOnly
+        } else if self.branches_only {
+            Filter::BranchesOnly
+        } else {
+            Filter::All
+        };
+
+        let render = || {
+            let commits = git.list_commits()?;
+            Ok(if self.json {
+                let stack = commits_to_json(commits, git.base_name());
+                serde_json::to_string_pretty(&stack).context("cannot serialize the stack")?
+            } else if let Some(format) = &self.format {
+                commits_to_formatted_string(commits, format)
+            } else if self.graph {
+                commits_to_graph(commits)
+            } else {
+                commits_to_colored_string(commits, filter, !self.no_tests, self.verbose)
+            })
+        };
+        let output = if self.no_cache {
+            render()?
+        } else {
+            let variant = format!(
+                "{:?} {} {} {} {} {} {:?}",
+                filter,
+                self.graph,
+                self.json,
+                self.no_tests,
+                self.verbose,
+                color::enabled(),
+                self.format
+            );
+            render_cached(&git, &variant, render)?
+        };
+        pager::page(
+            &format!("{}\n", output.trim()),
+            git.config.core.pager.as_deref(),
+        )
+    }
+
+    /// Print the titles edited in the todo that differ from the commits
+    fn preview_rewords(&self, git: &Git) -> Result<()> {
+        let content = git.edit(&commits_to_string(git.list_commits()?))?;
+        let edited = instruction_from_string(content).context("Cannot parse instruction")?;
+
         let commits = git.list_commits()?;
-        let output = commits_to_string(commits);
-        println!("{}", output.trim());
+        let rewords = pending_rewords(&commits, &edited);
+        if rewords.is_empty() {
+            println!("no commit title would change");
+            return Ok(());
+        }
+        print!("{}", rewords_to_string(&rewords));
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Show;
+    use crate::{
+        editor::{self, Editor},
+        git::{
+            tmp::{git, git_config, GitTmp},
+            Git, Ui,
+        },
+    };
+
+    /// An editor that is never available
+    struct FailingEditor;
+
+    impl Editor for FailingEditor {
+        fn edit(&self, _file_path: &str) -> anyhow::Result<String> {
+            Err(anyhow::Error::msg("no editor"))
+        }
+    }
+
+    fn show(json: bool) -> Show {
+        Show {
+            commits_only: false,
+            branches_only: false,
+            graph: false,
+            format: None,
+            json,
+            pending: false,
+            verbose: false,
+            no_tests: false,
+            no_cache: true,
+        }
+    }
+
+    fn stack() -> GitTmp {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        repo.commit("feature commit");
+        repo
+    }
+
+    #[test]
+    fn test_json_without_editor() {
+        let repo = stack();
+        git_config!(repo, "yggit.ui", "none");
+
+        let git = Git::open(&repo.path()).unwrap();
+        show(true)
+            .execute(git)
+            .expect("json does not need an editor");
+    }
+
+    #[test]
+    fn test_show_with_failing_editor() {
+        let repo = stack();
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_editor(Box::new(FailingEditor));
+        show(false)
+            .execute(git)
+            .expect("show does not open the editor");
+
+        // Only --pending edits the todo
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_editor(Box::new(FailingEditor));
+        let pending = Show {
+            pending: true,
+            ..show(false)
+        };
+        assert!(pending.execute(git).is_err());
+    }
+
+    #[test]
+    fn test_show_without_configured_editor() {
+        let repo = stack();
+        git!(repo, "config", "--unset", "core.editor");
+
+        // VISUAL or EDITOR may be set where the tests run, they are ignored
+        let open = || {
+            let mut git = Git::open(&repo.path()).expect("no editor is needed to open");
+            git.set_editor(editor::from_config(Ui::Terminal, None));
+            git
+        };
+        show(false)
+            .execute(open())
+            .expect("show does not need an editor");
+
+        let pending = Show {
+            pending: true,
+            ..show(false)
+        };
+        let error = pending.execute(open()).unwrap_err();
+        assert!(format!("{error:#}").contains("editor not found in configuration"));
+    }
+}
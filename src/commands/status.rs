@@ -0,0 +1,16 @@
+use crate::{core::status, git::Git};
+use anyhow::Result;
+use clap::Args;
+
+/// Print how each branch of the stack compares with its remote, without opening the editor
+#[derive(Debug, Args)]
+pub struct Status {}
+
+impl Status {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        for (origin, branch, status) in status(&git)? {
+            println!("{}:{} {}", origin, branch, status);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use crate::{
+    core::{refuse_if_in_progress, run_tests},
+    git::Git,
+};
+use anyhow::Result;
+use clap::Args;
+
+/// Run the command written `$ <command>` under each commit of the stack,
+/// like `git rebase -i --exec` with a command per commit
+#[derive(Debug, Args)]
+pub struct Test {}
+
+impl Test {
+    pub fn execute(&self, git: Git) -> Result<()> {
+        refuse_if_in_progress(&git)?;
+
+        let tested = run_tests(&git)?;
+        if tested == 0 {
+            println!("no commit has a test command, add one with `$ <command>` under a commit");
+        } else {
+            println!("{} commits tested", tested);
+        }
+        Ok(())
+    }
+}
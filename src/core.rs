@@ -1,37 +1,500 @@
 use crate::{
-    git::{EnhancedCommit, Git},
-    parser::Target,
+    git::{BranchName, EnhancedCommit, Git, Origin, PushOutcome, RefTypeConflict, TrackingStatus},
+    parser::{single_line, TagTarget, Target},
 };
 use anyhow::{Context, Result};
+use git2::{ErrorClass, ErrorCode, Oid};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, IsTerminal, Write},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex, PoisonError},
+    time::Duration,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct Push {
-    pub origin: Option<String>,
-    pub branch: String,
+    pub origin: Option<Origin>,
+    pub branch: BranchName,
+    /// Overrides the strategy of the push command for this branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<PushStrategy>,
+}
+
+/// How a branch is pushed, written `(force)`, `(lease)` or `(normal)` after it in the todo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushStrategy {
+    /// Like `git push --force`
+    Force,
+    /// Like `git push --force-with-lease`, the default
+    Lease,
+    /// Like `git push`, the remote branch is never rewound
+    Normal,
+}
+
+impl PushStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PushStrategy::Force => "force",
+            PushStrategy::Lease => "lease",
+            PushStrategy::Normal => "normal",
+        }
+    }
 }
 
+/// A tag to create on a commit
+///
+/// The tag is annotated when it has a message, lightweight otherwise
 #[derive(Deserialize, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
 pub struct Note {
-    pub push: Option<Push>,
+    /// Branches pushed from the commit, in the order of the todo
+    #[serde(rename = "push", default, with = "one_or_many")]
+    pub pushes: Vec<Push>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<Tag>,
+    /// Comments written in the todo above the commit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
+    /// Metadata stored by other commands, see `set_commit_meta`
+    #[serde(flatten)]
+    pub meta: Map<String, Value>,
+}
+
+/// A list stored like a single value when it has one element, and as null when empty
+///
+/// The notes written before several branches per commit stay readable
+mod one_or_many {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    pub fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        match values {
+            [] => serializer.serialize_none(),
+            [value] => value.serialize(serializer),
+            values => values.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::<OneOrMany<T>>::deserialize(deserializer)? {
+            None => Vec::default(),
+            Some(OneOrMany::One(value)) => vec![value],
+            Some(OneOrMany::Many(values)) => values,
+        })
+    }
+}
+
+/// Metadata key of the command testing a commit
+pub const TEST_KEY: &str = "test";
+
+impl Note {
+    /// Returns the command testing the commit, if any
+    pub fn test_command(&self) -> Option<&str> {
+        self.meta.get(TEST_KEY).and_then(Value::as_str)
+    }
+}
+
+/// Keys of the note managed by yggit itself,
+/// they cannot be used as commit metadata
+#[cfg(test)]
+pub(crate) const RESERVED_KEYS: &[&str] = &["push", "tag", "comments", TEST_KEY];
+
+/// Warn the user when the repository is a shallow clone
+///
+/// Returns true if the warning has been printed
+pub fn warn_if_shallow(git: &Git) -> bool {
+    let is_shallow = git.is_shallow();
+    if is_shallow {
+        eprintln!("warning: the repository is a shallow clone, force-with-lease and ancestor checks may be unreliable");
+        eprintln!("hint: run `git fetch --unshallow` to fetch the complete history");
+    }
+    is_shallow
+}
+
+/// Refuse to change the repository while a git operation, like a rebase or a merge, is in progress
+pub fn refuse_if_in_progress(git: &Git) -> Result<()> {
+    match git.operation_in_progress() {
+        Some(operation) => Err(anyhow::Error::msg(format!(
+            "{operation} in progress; finish or abort it first"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Warn the user when a git operation, like a rebase or a merge, is in progress
+///
+/// Returns true if the warning has been printed
+pub fn warn_if_in_progress(git: &Git) -> bool {
+    let operation = git.operation_in_progress();
+    if let Some(operation) = operation {
+        eprintln!("warning: {operation} in progress, the stack may be incomplete");
+    }
+    operation.is_some()
+}
+
+/// Store a typed metadata under the given key of the commit's note
+///
+/// The other keys of the note are kept
+#[cfg(test)]
+pub(crate) fn set_commit_meta<T>(git: &Git, oid: Oid, key: &str, value: &T) -> Result<()>
+where
+    T: Serialize,
+{
+    if RESERVED_KEYS.contains(&key) {
+        return Err(anyhow::Error::msg(format!("{key} is reserved by yggit")));
+    }
+    let mut note = git.find_note::<Note>(oid)?.unwrap_or_default();
+    let value = serde_json::to_value(value).context("Cannot convert metadata to json")?;
+    note.meta.insert(key.to_string(), value);
+    git.set_note(oid, note)
+        .context("Cannot write note to commit")?;
+    Ok(())
+}
+
+/// Returns the typed metadata stored under the given key of the commit's note
+#[cfg(test)]
+pub(crate) fn get_commit_meta<T>(git: &Git, oid: Oid, key: &str) -> Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let Some(mut note) = git.find_note::<Note>(oid)? else {
+        return Ok(None);
+    };
+    let Some(value) = note.meta.remove(key) else {
+        return Ok(None);
+    };
+    serde_json::from_value(value)
+        .map(Some)
+        .with_context(|| format!("Cannot parse metadata {key}"))
+}
+
+/// Same as `get_commit_meta`, with the default value when the key is missing
+#[cfg(test)]
+pub(crate) fn get_commit_meta_or_default<T>(git: &Git, oid: Oid, key: &str) -> Result<T>
+where
+    T: serde::de::DeserializeOwned + Default,
+{
+    get_commit_meta(git, oid, key).map(Option::unwrap_or_default)
+}
+
+/// Same as `get_commit_meta`, a malformed value is treated as a missing one
+#[cfg(test)]
+pub(crate) fn try_get_commit_meta<T>(git: &Git, oid: Oid, key: &str) -> Option<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    get_commit_meta(git, oid, key).ok().flatten()
+}
+
+/// Returns every key stored in the commit's note, with its value
+///
+/// The keys of yggit are included, a commit without note returns an empty map
+#[cfg(test)]
+pub(crate) fn read_all_meta(git: &Git, oid: Oid) -> Result<Map<String, Value>> {
+    Ok(match git.find_note::<Value>(oid)? {
+        Some(Value::Object(map)) => map,
+        _ => Map::default(),
+    })
+}
+
+/// Returns the top-level keys of the commit's note, empty when there is no note
+#[cfg(test)]
+pub(crate) fn meta_keys(git: &Git, oid: Oid) -> Result<Vec<String>> {
+    Ok(read_all_meta(git, oid)?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect())
+}
+
+/// A branch of the todo that git would refuse to create
+#[derive(Debug)]
+pub struct InvalidBranchName {
+    pub name: String,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for InvalidBranchName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid branch name {:?}: {}", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidBranchName {}
+
+/// Check the name of a branch with the rules of `git check-ref-format --branch`
+pub fn validate_branch_name(branch: &BranchName) -> Result<(), InvalidBranchName> {
+    let name = branch.as_str();
+    let reason = if name.is_empty() {
+        Some("it is empty")
+    } else if name == "@" || name == "HEAD" {
+        Some("it is reserved by git")
+    } else if name.starts_with('-') {
+        Some("it starts with -")
+    } else if name.starts_with('/') || name.ends_with('/') || name.contains("//") {
+        Some("it has an empty component")
+    } else if name.contains("..") {
+        Some("it contains ..")
+    } else if name.contains("@{") {
+        Some("it contains @{")
+    } else if name.ends_with('.') {
+        Some("it ends with .")
+    } else if name
+        .split('/')
+        .any(|component| component.starts_with('.') || component.ends_with(".lock"))
+    {
+        Some("a component starts with . or ends with .lock")
+    } else if name
+        .chars()
+        .any(|c| c.is_ascii_control() || c.is_whitespace() || "~^:?*[\\".contains(c))
+    {
+        Some("it contains a space, a control character or one of ~^:?*[\\")
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(InvalidBranchName {
+            name: name.to_string(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The same branch is written under two commits of the todo
+#[derive(Debug)]
+pub struct DuplicateBranch {
+    pub origin: Option<Origin>,
+    pub name: BranchName,
+    pub first: Oid,
+    pub second: Oid,
+}
+
+impl std::fmt::Display for DuplicateBranch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let target = match &self.origin {
+            Some(origin) => format!("{}:{}", origin, self.name),
+            None => self.name.to_string(),
+        };
+        let (first, second) = (self.first.to_string(), self.second.to_string());
+        write!(
+            f,
+            "{} is set on both {} and {}, keep only one of them",
+            target,
+            &first[..7],
+            &second[..7]
+        )
+    }
+}
+
+impl std::error::Error for DuplicateBranch {}
+
+/// Check the parsed todo before anything is written, the notes nor the branches
+pub fn validate_todo(commits: &[crate::parser::Commit]) -> Result<()> {
+    let mut seen: HashMap<(Option<&Origin>, &BranchName), Oid> = HashMap::default();
+    for commit in commits {
+        for Target { branch, .. } in commit.targets.iter().chain(&commit.deletions) {
+            validate_branch_name(branch)?;
+        }
+        for Target { origin, branch, .. } in &commit.targets {
+            let first = *seen.entry((origin.as_ref(), branch)).or_insert(commit.hash);
+            if first != commit.hash {
+                return Err(DuplicateBranch {
+                    origin: origin.clone(),
+                    name: branch.clone(),
+                    first,
+                    second: commit.hash,
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Number of times the todo is opened, until it can be parsed
+const MAX_EDIT_ATTEMPTS: usize = 3;
+
+/// Prefix of the lines explaining why the todo has been opened again, they are not parsed
+const EDIT_ERROR_PREFIX: &str = "# yggit error: ";
+
+/// Line delimiting the help of the todo, it and everything below it are ignored, like in git commit --verbose
+pub const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Split the text at the scissors line, the second part starts with it and is empty without it
+fn cut_at_scissors(text: &str) -> (&str, &str) {
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_end() == SCISSORS {
+            return text.split_at(start);
+        }
+        start += line.len();
+    }
+    (text, "")
+}
+
+/// Name of the file, in the git directory, keeping the last todo that could not be parsed
+pub const RECOVER_FILE: &str = "yggit-recover";
+
+/// Let the user edit the todo, then parse and validate it
+///
+/// When the edited todo is not valid, it is saved in `RECOVER_FILE` so that it is not lost,
+/// and opened again with the error at the top, until `MAX_EDIT_ATTEMPTS` or an empty todo
+///
+/// Everything from the `SCISSORS` line is ignored, and given back when the todo is opened again
+pub fn edit_todo(git: &Git, todo: &str) -> Result<Vec<crate::parser::Commit>> {
+    let recover_path = git.git_dir().join(RECOVER_FILE);
+    let (_, help) = cut_at_scissors(todo);
+    let mut todo = todo.to_string();
+    let mut attempts = 0;
+    let mut recovered = false;
+    loop {
+        attempts += 1;
+        let edited = git.edit(&todo)?;
+        let (edited, _) = cut_at_scissors(&edited);
+        let mut content = String::default();
+        for line in edited.lines() {
+            if !line.starts_with(EDIT_ERROR_PREFIX) {
+                content = format!("{content}{line}\n");
+            }
+        }
+        let result = crate::parser::instruction_from_string(content.clone())
+            .and_then(|commits| validate_todo(&commits).map(|()| commits));
+        let error = match result {
+            Ok(commits) => return Ok(commits),
+            Err(error) => error,
+        };
+
+        // An editor reading stdin, for instance, gives nothing the second time
+        if !content.trim().is_empty() {
+            std::fs::write(&recover_path, &content).context("Cannot save the edited todo")?;
+            recovered = true;
+        }
+        if attempts == MAX_EDIT_ATTEMPTS || content.trim().is_empty() {
+            if !recovered {
+                return Err(error);
+            }
+            return Err(error.context(format!(
+                "Cannot parse instruction, the edited todo has been saved in {}",
+                recover_path.display()
+            )));
+        }
+
+        eprintln!("{error:#}, the todo is opened again");
+        todo = String::default();
+        for line in format!("{error:#}").lines() {
+            todo = format!("{todo}{EDIT_ERROR_PREFIX}{line}\n");
+        }
+        todo = format!("{todo}{content}{help}");
+    }
+}
+
+/// Returns true if the branch is selected by `--only`, every branch is selected when it is empty
+fn is_selected(only: &[BranchName], branch: &BranchName) -> bool {
+    only.is_empty() || only.contains(branch)
+}
+
+/// Keep the parsed commits that have, or used to have, one of the selected branches
+///
+/// The mappings of the other commits are left untouched
+pub fn restrict_to_branches(
+    git: &Git,
+    commits: Vec<crate::parser::Commit>,
+    only: &[BranchName],
+) -> Result<Vec<crate::parser::Commit>> {
+    if only.is_empty() {
+        return Ok(commits);
+    }
+    let mut restricted = Vec::default();
+    for commit in commits {
+        let current = git
+            .find_note::<Note>(commit.hash)?
+            .map(|note| note.pushes)
+            .unwrap_or_default();
+        let new = commit.targets.iter().map(|target| &target.branch);
+        if new
+            .chain(current.iter().map(|push| &push.branch))
+            .any(|branch| is_selected(only, branch))
+        {
+            restricted.push(commit);
+        }
+    }
+    Ok(restricted)
 }
 
 /// Save the note to the commit
 ///
 /// Also deletes note if there is nothing new
+/// The metadata of the existing note are kept, except the test command which comes from the todo
 pub fn save_note(git: &Git, commits: Vec<crate::parser::Commit>) -> Result<()> {
     for commit in commits {
         // Extract information from commit
-        let crate::parser::Commit { hash, target, .. } = commit;
+        let crate::parser::Commit {
+            hash,
+            targets,
+            tag,
+            comments,
+            test,
+            ..
+        } = commit;
+
+        let mut meta = git
+            .find_note::<Note>(hash)?
+            .map(|note| note.meta)
+            .unwrap_or_default();
+        match test {
+            Some(command) => meta.insert(TEST_KEY.to_string(), Value::String(command)),
+            None => meta.remove(TEST_KEY),
+        };
 
-        let is_empty = target.is_none();
+        let is_empty =
+            targets.is_empty() && tag.is_none() && comments.is_empty() && meta.is_empty();
 
         if is_empty {
             git.delete_note(&hash)?;
         } else {
             // Create the note
             let note = Note {
-                push: target.map(|Target { origin, branch }| Push { origin, branch }),
+                pushes: targets
+                    .into_iter()
+                    .map(
+                        |Target {
+                             origin,
+                             branch,
+                             strategy,
+                         }| Push {
+                            origin,
+                            branch,
+                            strategy,
+                        },
+                    )
+                    .collect(),
+                tag: tag.map(|TagTarget { name, message }| Tag { name, message }),
+                comments,
+                meta,
             };
 
             // Save the note
@@ -42,18 +505,193 @@ pub fn save_note(git: &Git, commits: Vec<crate::parser::Commit>) -> Result<()> {
     Ok(())
 }
 
+/// A commit whose title has been changed in the todo
+#[derive(Debug, PartialEq)]
+pub struct Reword {
+    pub id: Oid,
+    pub old: String,
+    pub new: String,
+}
+
+/// Compare the titles edited in the todo with the titles of the commits
+///
+/// Nothing is rewritten, the changes are only returned, from the base of the stack to its tip
+pub fn pending_rewords(
+    commits: &[EnhancedCommit<Note>],
+    edited: &[crate::parser::Commit],
+) -> Vec<Reword> {
+    commits
+        .iter()
+        .filter_map(|commit| {
+            let edited = edited.iter().find(|edited| edited.hash == commit.id)?;
+            // The todo only has the first line of the title
+            let old = single_line(&commit.title);
+            (old != edited.title).then(|| Reword {
+                id: commit.id,
+                old,
+                new: edited.title.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Move the note of a commit to another one, for instance after an amend
+///
+/// The note of the new commit is only replaced with force
+pub fn migrate_note(git: &Git, from: Oid, to: Oid, force: bool) -> Result<()> {
+    if from == to {
+        return Err(anyhow::Error::msg("the note is already on this commit"));
+    }
+    if !git.has_note(from)? {
+        return Err(anyhow::Error::msg(format!("{from} has no note")));
+    }
+    if !force && git.has_note(to)? {
+        return Err(anyhow::Error::msg(format!(
+            "{to} already has a note, use --force to replace it"
+        )));
+    }
+    git.copy_note(from, to)?;
+    git.delete_note(&from)
+}
+
+/// Remove the branches and the tags from the notes of the stack
+///
+/// The metadata and the comments of the notes are kept, the notes left empty are deleted
+/// Returns the number of cleared commits
+pub fn reset_notes(git: &Git) -> Result<usize> {
+    let commits = git.list_commits::<Note>()?;
+    let mut cleared = 0;
+    for commit in commits {
+        let Some(note) = commit.note else {
+            continue;
+        };
+        if note.pushes.is_empty() && note.tag.is_none() {
+            continue;
+        }
+        if note.meta.is_empty() && note.comments.is_empty() {
+            git.delete_note(&commit.id)?;
+        } else {
+            let note = Note {
+                comments: note.comments,
+                meta: note.meta,
+                ..Default::default()
+            };
+            git.set_note(commit.id, note)
+                .context("Cannot write note to commit")?;
+        }
+        cleared += 1;
+    }
+    Ok(cleared)
+}
+
+/// Remove the branches from the notes of the stack, for instance once it has been merged
+///
+/// Unlike `reset_notes`, the tags are kept with the comments and the metadata,
+/// the notes left empty are deleted
+/// Returns the number of notes whose branches have been removed
+pub fn clean_branches(git: &Git) -> Result<usize> {
+    let commits = git.list_commits::<Note>()?;
+    let mut removed = 0;
+    for commit in commits {
+        let Some(note) = commit.note else {
+            continue;
+        };
+        if note.pushes.is_empty() {
+            continue;
+        }
+        if note.tag.is_none() && note.meta.is_empty() && note.comments.is_empty() {
+            git.delete_note(&commit.id)?;
+        } else {
+            let note = Note {
+                pushes: Vec::default(),
+                ..note
+            };
+            git.set_note(commit.id, note)
+                .context("Cannot write note to commit")?;
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Returns the branches to move with their new head
+///
+/// The commits are ordered from the base of the stack to its tip,
+/// so parent branches are moved before their children
+fn branches_to_move(commits: &[EnhancedCommit<Note>]) -> Vec<(&Push, Oid)> {
+    commits
+        .iter()
+        .flat_map(|commit| {
+            commit
+                .note
+                .iter()
+                .flat_map(|note| &note.pushes)
+                .map(|push| (push, commit.id))
+        })
+        .collect()
+}
+
+/// Compare each branch of the notes with its remote-tracking branch, from the base to the tip
+///
+/// Nothing is pushed nor fetched
+pub fn status(git: &Git) -> Result<Vec<(Origin, BranchName, TrackingStatus)>> {
+    let commits = git.list_commits()?;
+    branches_to_move(&commits)
+        .into_iter()
+        .map(|(Push { origin, branch, .. }, id)| {
+            let origin = origin
+                .clone()
+                .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+            let status = git.tracking_status(&origin, branch, id)?;
+            Ok((origin, branch.clone(), status))
+        })
+        .collect()
+}
+
 /// Execute the instructions from the notes
 /// to change the head of the given branches
-pub fn apply(git: &Git) -> Result<()> {
+///
+/// When show_diff is set, a diff stat between the previous and the new tip of each moved branch is printed
+/// When only is not empty, only these branches and the tags of their commits are updated
+pub fn apply(git: &Git, show_diff: bool, only: &[BranchName]) -> Result<PushReport> {
     let commits = git.list_commits()?;
+    let mut report = PushReport::default();
+
+    // Update the commits, from the base to the tip
+    for (Push { origin, branch, .. }, id) in branches_to_move(&commits) {
+        if !is_selected(only, branch) {
+            continue;
+        }
+        // A new branch tracks the branch it will be pushed to
+        let origin = origin
+            .clone()
+            .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+        // Set the head of the branch to the given commit
+        let previous = git.set_branch_to_commit(branch, id, Some(&origin))?; // TODO: manage error
+        match previous {
+            Some(previous) if show_diff && previous != id => {
+                let stat = git.diff_stat(previous, id)?;
+                println!("{}: {}", branch, stat);
+            }
+            _ => (),
+        }
+        report.pushed.push(BranchOutcome {
+            origin,
+            branch: branch.clone(),
+            old: previous,
+            new: id,
+            status: BranchStatus::NotPushed,
+        });
+    }
 
-    // Update the commits
+    // Create the tags
     for commit in &commits {
         let EnhancedCommit {
             id,
             note:
                 Some(Note {
-                    push: Some(Push { branch, origin: _ }),
+                    pushes,
+                    tag: Some(Tag { name, message }),
                     ..
                 }),
             ..
@@ -61,42 +699,2226 @@ pub fn apply(git: &Git) -> Result<()> {
         else {
             continue;
         };
-        // Set the head of the branch to the given commit
-        git.set_branch_to_commit(branch, *id)?; // TODO: manage error
+        let selected = only.is_empty() || pushes.iter().any(|push| is_selected(only, &push.branch));
+        if !selected {
+            continue;
+        }
+        match message {
+            Some(message) => git.create_annotated_tag(name, *id, message, true)?,
+            None => git.create_tag(name, *id, true)?,
+        }
+    }
+    Ok(report)
+}
+
+/// Delete from their remote the branches marked with `-> !<branch>` in the todo
+///
+/// The branch is not written in the note anymore, so it will not be pushed again
+pub fn delete_branches(
+    git: &Git,
+    commits: &[crate::parser::Commit],
+    only: &[BranchName],
+) -> Result<Vec<BranchOutcome>> {
+    let mut outcomes = Vec::default();
+    for commit in commits {
+        for Target { origin, branch, .. } in &commit.deletions {
+            if !is_selected(only, branch) {
+                continue;
+            }
+            let origin = origin
+                .clone()
+                .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+            let old = git.remote_branch_oid(&origin, branch);
+            let status = if git.delete_remote_branch(&origin, branch)? {
+                BranchStatus::Deleted
+            } else {
+                BranchStatus::NothingToDelete
+            };
+            outcomes.push(BranchOutcome {
+                origin,
+                branch: branch.clone(),
+                old,
+                new: commit.hash,
+                status,
+            });
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Describe what `save_note`, `apply` and `push_from_notes` would do with the edited commits
+///
+/// Nothing is written, the diff stat of each moved branch is computed like `apply --show-diff`,
+/// and the remote is asked whether it would accept the push, without updating it
+pub fn push_plan(
+    git: &Git,
+    commits: &[crate::parser::Commit],
+    only: &[BranchName],
+    force: bool,
+) -> Result<Vec<String>> {
+    let mut plan = Vec::default();
+    for commit in commits {
+        let short = &commit.hash.to_string()[..7];
+        let mut selected = only.is_empty();
+        for Target {
+            origin,
+            branch,
+            strategy,
+        } in &commit.targets
+        {
+            if !is_selected(only, branch) {
+                continue;
+            }
+            selected = true;
+            let origin = origin
+                .clone()
+                .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+            let line = match git.branch_commit(branch) {
+                None => format!("would create branch {branch} on {short} and push to {origin}"),
+                Some(previous) if previous == commit.hash => {
+                    format!("would keep branch {branch} on {short} and push to {origin}")
+                }
+                Some(previous) => format!(
+                    "would set branch {branch} to {short} and push to {origin} ({})",
+                    git.diff_stat(previous, commit.hash)?
+                ),
+            };
+            let strategy = strategy.unwrap_or(if force {
+                PushStrategy::Force
+            } else {
+                PushStrategy::Lease
+            });
+            plan.push(match git.dry_push(&origin, branch, commit.hash, strategy) {
+                Ok(PushOutcome::Diverged) => {
+                    format!("{line}, but {origin}:{branch} has diverged, the push would be refused")
+                }
+                Ok(_) => line,
+                Err(error) => format!("{line}, but {origin} cannot be checked: {error:#}"),
+            });
+        }
+        if let (true, Some(TagTarget { name, .. })) = (selected, &commit.tag) {
+            plan.push(format!("would tag {short} as {name}"));
+        }
+        for Target { origin, branch, .. } in &commit.deletions {
+            if !is_selected(only, branch) {
+                continue;
+            }
+            let origin = origin
+                .clone()
+                .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+            plan.push(format!("would delete {origin}:{branch}"));
+        }
+    }
+    Ok(plan)
+}
+
+/// Replay the stack on top of the new base
+///
+/// The notes follow the rewritten commits, and the branches are moved to them.
+/// The note of a commit already applied on the new base is merged into the note of the commit containing its change,
+/// and its branches are moved there
+pub fn rebase(git: &Git, new_base: &str) -> Result<()> {
+    let rebased = git.rebase_onto(new_base)?;
+
+    let commits: Vec<EnhancedCommit<Note>> = rebased
+        .rewritten
+        .iter()
+        .filter_map(|(_, new)| git.find_commit(*new).transpose())
+        .collect::<Result<_>>()?;
+
+    for (Push { branch, .. }, id) in branches_to_move(&commits) {
+        git.set_branch_to_commit(branch, id, None)?;
+    }
+
+    let short = |oid: Oid| oid.to_string()[..7].to_string();
+    for (old, containing) in rebased.applied {
+        let Some(note) = git.find_note::<Note>(old)? else {
+            continue;
+        };
+        for Push { branch, .. } in &note.pushes {
+            eprintln!(
+                "warning: {} is already applied on {new_base}, {branch} is moved to {}",
+                short(old),
+                short(containing)
+            );
+            git.set_branch_to_commit(branch, containing, None)?;
+        }
+        let mut merged = git.find_note::<Note>(containing)?.unwrap_or_default();
+        merged.pushes.extend(note.pushes);
+        match (&merged.tag, note.tag) {
+            (Some(_), Some(Tag { name, .. })) => eprintln!(
+                "warning: {} already has a tag, the tag {name} of {} is dropped",
+                short(containing),
+                short(old)
+            ),
+            (None, tag) => merged.tag = tag,
+            (Some(_), None) => (),
+        }
+        merged.comments.extend(note.comments);
+        for (key, value) in note.meta {
+            merged.meta.entry(key).or_insert(value);
+        }
+        git.set_note(containing, merged)
+            .context("Cannot write note to commit")?;
     }
     Ok(())
 }
 
+/// The test command of a commit failed while testing the stack
+#[derive(Debug)]
+pub struct TestFailed {
+    pub commit: Oid,
+    pub title: String,
+    pub command: String,
+}
+
+impl std::fmt::Display for TestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = self.commit.to_string();
+        write!(
+            f,
+            "`{}` failed on {} {}, fix it and run git rebase --continue, or git rebase --abort",
+            self.command,
+            &id[..7],
+            self.title
+        )
+    }
+}
+
+impl std::error::Error for TestFailed {}
+
+/// Todo of `git rebase -i` picking the commits, each one followed by its test command
+fn test_todo(commits: &[EnhancedCommit<Note>]) -> String {
+    let mut todo = String::default();
+    for commit in commits {
+        todo = format!("{}pick {} {}\n", todo, commit.id, commit.title);
+        if let Some(command) = commit.note.as_ref().and_then(Note::test_command) {
+            todo = format!("{}exec {}\n", todo, command);
+        }
+    }
+    todo
+}
+
+/// Run the test command of each commit of the stack, from its base to its tip
+///
+/// The commits are not rewritten, the rebase only checks them out one after the other.
+/// Returns the number of tested commits
+pub fn run_tests(git: &Git) -> Result<usize> {
+    let commits = git.list_commits::<Note>()?;
+    let tested = commits
+        .iter()
+        .filter(|commit| commit.note.as_ref().and_then(Note::test_command).is_some())
+        .count();
+    if tested == 0 {
+        return Ok(0);
+    }
+
+    if git.rebase_with_todo(&test_todo(&commits))? {
+        return Ok(tested);
+    }
+
+    // The rebase stops on the commit whose test failed
+    let (head, _, _) = git.stack_state()?;
+    let failed = commits
+        .into_iter()
+        .find(|commit| commit.id == head)
+        .context("the rebase stopped before running the tests")?;
+    Err(TestFailed {
+        commit: failed.id,
+        command: failed
+            .note
+            .as_ref()
+            .and_then(Note::test_command)
+            .unwrap_or_default()
+            .to_string(),
+        title: failed.title,
+    }
+    .into())
+}
+
+/// Returns the order of the commits of the edited todo, from the base to the tip,
+/// when it differs from the order of the stack
+///
+/// Only a pure reordering is returned, not when commits are missing or repeated in the todo
+pub fn reordered(
+    commits: &[EnhancedCommit<Note>],
+    edited: &[crate::parser::Commit],
+) -> Option<Vec<Oid>> {
+    let order: Vec<Oid> = edited.iter().map(|commit| commit.hash).collect();
+    let current: Vec<Oid> = commits.iter().map(|commit| commit.id).collect();
+    if order == current {
+        return None;
+    }
+    let mut sorted_order = order.clone();
+    let mut sorted_current = current;
+    sorted_order.sort();
+    sorted_current.sort();
+    (sorted_order == sorted_current).then_some(order)
+}
+
+/// Replay the commits of the stack in the given order, from the base to the tip
+///
+/// The notes follow the rewritten commits through notes.rewriteRef.
+/// If a conflict happens the rebase is aborted and HEAD is restored
+pub fn reorder(git: &Git, order: &[Oid]) -> Result<()> {
+    let mut todo = String::default();
+    for oid in order {
+        todo = format!("{}pick {}\n", todo, oid);
+    }
+    if git.rebase_with_todo(&todo)? {
+        return Ok(());
+    }
+    if git.operation_in_progress().is_none() {
+        return Err(anyhow::Error::msg(
+            "git rebase failed, the commits have not been reordered",
+        ));
+    }
+    git.abort_rebase()?;
+    Err(anyhow::Error::msg(
+        "conflict while reordering the commits, the rebase has been aborted",
+    ))
+}
+
+/// Branches already pushed by an unfinished push
+///
+/// It is saved in the git directory, so that an interrupted push can be resumed
+#[derive(Deserialize, Serialize, Default, Debug, PartialEq)]
+pub struct Progress {
+    pub force: bool,
+    pub pushed: Vec<(Origin, BranchName)>,
+    /// Branches selected by --only, all of them when empty
+    #[serde(default)]
+    pub only: Vec<BranchName>,
+    /// Fetch the origins before checking the leases
+    #[serde(default)]
+    pub fetch: bool,
+    /// Origin of the branches without one, given by --origin, yggit.defaultUpstream when None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// Push the branches of each origin in a single atomic push
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+impl Progress {
+    fn path(git_dir: &Path) -> PathBuf {
+        git_dir.join("yggit-progress")
+    }
+
+    /// Returns the progress of an interrupted push, if any
+    pub fn load(git_dir: &Path) -> Result<Option<Progress>> {
+        let path = Self::path(git_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path).context("Cannot read push progress")?;
+        let progress = serde_json::from_str(&content).context("Cannot parse push progress")?;
+        Ok(Some(progress))
+    }
+
+    fn save(&self, git_dir: &Path) -> Result<()> {
+        let content = serde_json::to_string(self).context("Cannot serialize push progress")?;
+        std::fs::write(Self::path(git_dir), content).context("Cannot write push progress")
+    }
+
+    /// Removes the progress, the push is finished
+    pub fn clear(git_dir: &Path) -> Result<()> {
+        let path = Self::path(git_dir);
+        if path.exists() {
+            std::fs::remove_file(path).context("Cannot remove push progress")?;
+        }
+        Ok(())
+    }
+
+    fn is_pushed(&self, origin: &Origin, branch: &BranchName) -> bool {
+        self.pushed.iter().any(|(pushed_origin, pushed_branch)| {
+            pushed_origin == origin && pushed_branch == branch
+        })
+    }
+}
+
+/// Rendered stack, stored in `<git_dir>/yggit-cache`
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+struct ShowCache {
+    /// HEAD, base of the stack, notes and variant of the rendering
+    key: String,
+    output: String,
+}
+
+impl ShowCache {
+    fn path(git_dir: &Path) -> PathBuf {
+        git_dir.join("yggit-cache")
+    }
+
+    /// Returns the cached output, if it has been rendered with the same key
+    fn load(git_dir: &Path, key: &str) -> Option<String> {
+        let content = std::fs::read_to_string(Self::path(git_dir)).ok()?;
+        // An unreadable cache is regenerated
+        let cache: ShowCache = serde_json::from_str(&content).ok()?;
+        (cache.key == key).then_some(cache.output)
+    }
+
+    fn save(&self, git_dir: &Path) -> Result<()> {
+        let content = serde_json::to_string(self).context("Cannot serialize show cache")?;
+        std::fs::write(Self::path(git_dir), content).context("Cannot write show cache")
+    }
+}
+
+/// Render the stack, the output is reused until HEAD, the base of the stack or the notes move
+///
+/// The variant differentiates the renderings of a same stack
+pub fn render_cached<F>(git: &Git, variant: &str, render: F) -> Result<String>
+where
+    F: FnOnce() -> Result<String>,
+{
+    let (head, base, notes) = git.stack_state()?;
+    let notes = notes.map(|notes| notes.to_string()).unwrap_or_default();
+    let key = format!("{} {} {} {}", head, base, notes, variant);
+
+    if let Some(output) = ShowCache::load(git.git_dir(), &key) {
+        return Ok(output);
+    }
+    let output = render()?;
+    ShowCache {
+        key,
+        output: output.clone(),
+    }
+    .save(git.git_dir())?;
+    Ok(output)
+}
+
+/// Ask a yes/no question before an action that cannot be undone, no is the default answer
+///
+/// It fails when stdin is not a terminal, nothing is destroyed without a human answering
+pub fn confirm_destructive(question: &str) -> Result<bool> {
+    let stdin = std::io::stdin();
+    let is_terminal = stdin.is_terminal();
+    confirm_destructive_with(question, is_terminal, &mut stdin.lock())
+}
+
+fn confirm_destructive_with(
+    question: &str,
+    is_terminal: bool,
+    input: &mut impl BufRead,
+) -> Result<bool> {
+    if !is_terminal {
+        return Err(anyhow::Error::msg(format!(
+            "{question} needs an answer but stdin is not a terminal"
+        )));
+    }
+    print!("{} [y/N] ", question);
+    std::io::stdout()
+        .flush()
+        .context("Cannot write to stdout")?;
+    let mut answer = String::default();
+    input
+        .read_line(&mut answer)
+        .context("Cannot read from stdin")?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Ask a yes/no question to the user, yes is the default answer
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{} [Y/n] ", question);
+    std::io::stdout()
+        .flush()
+        .context("Cannot write to stdout")?;
+    let mut answer = String::default();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Cannot read from stdin")?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer.is_empty() || answer == "y" || answer == "yes")
+}
+
 /// Push the branches to origin
 ///
 /// If force is set to true it will use --force
 /// Otherwise it uses --force-with-lease
-pub fn push_from_notes(git: &Git, force: bool) -> Result<()> {
+/// When only is not empty, the other branches are not pushed
+/// When atomic is set, the branches of an origin are either all updated or none is
+pub fn push_from_notes(
+    git: &Git,
+    force: bool,
+    max_retries: u32,
+    only: Vec<BranchName>,
+    fetch: bool,
+    origin: Option<Origin>,
+    atomic: bool,
+) -> Result<PushReport> {
+    resume_push(
+        git,
+        Progress {
+            force,
+            pushed: Vec::default(),
+            only,
+            fetch,
+            origin,
+            atomic,
+        },
+        max_retries,
+    )
+}
+
+/// Returns true if the error is a network failure that may succeed when retried
+///
+/// Rejections from the remote and authentication failures are definitive
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let Some(error) = cause.downcast_ref::<git2::Error>() else {
+            return false;
+        };
+        let definitive = matches!(
+            error.code(),
+            ErrorCode::Auth | ErrorCode::Certificate | ErrorCode::NotFastForward
+        );
+        let network = matches!(
+            error.class(),
+            ErrorClass::Net | ErrorClass::Os | ErrorClass::Ssh | ErrorClass::Http
+        );
+        network && !definitive
+    })
+}
+
+/// Run the action again while it fails with a transient error, up to max_retries times
+///
+/// The delay between two attempts is doubled after each of them
+fn retry_transient<T, F>(max_retries: u32, delay: Duration, mut action: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut delay = delay;
+    let mut retries = 0;
+    loop {
+        match action() {
+            Err(error) if retries < max_retries && is_transient(&error) => {
+                retries += 1;
+                eprintln!(
+                    "warning: {error:#}, retrying in {}ms ({retries}/{max_retries})",
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Push a branch to the origin
+///
+/// It runs in the push workers, so it never asks anything,
+/// a branch rejected because of a tag of the same name is reported as a `RefTypeConflict`
+/// Transient network failures are retried up to max_retries times
+fn push_branch(
+    git: &Git,
+    origin: &Origin,
+    branch: &BranchName,
+    strategy: PushStrategy,
+    max_retries: u32,
+) -> Result<PushOutcome> {
+    retry_transient(max_retries, Duration::from_millis(500), || match strategy {
+        PushStrategy::Force => git.push_force(origin, branch),
+        PushStrategy::Lease => git.push_force_with_lease(origin, branch),
+        PushStrategy::Normal => git.push_normal(origin, branch),
+    })
+}
+
+/// What happened to a branch during a push
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BranchStatus {
+    /// The branch has been created on the remote
+    New,
+    /// The branch of the remote has been updated
+    Pushed,
+    /// The remote was already on the commit
+    UpToDate,
+    /// The push failed, with the reason
+    Failed(String),
+    /// The branch has only been moved locally, by `apply`
+    NotPushed,
+    /// The branch has been pushed before the resumed push was interrupted
+    AlreadyPushed,
+    /// The branch has been deleted from the remote, with `-> !<branch>`
+    Deleted,
+    /// The branch to delete was not on the remote
+    NothingToDelete,
+}
+
+/// Outcome of a branch of the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchOutcome {
+    pub origin: Origin,
+    pub branch: BranchName,
+    /// Commit of the branch before, on the remote for a push and locally for `apply`
+    pub old: Option<Oid>,
+    pub new: Oid,
+    pub status: BranchStatus,
+}
+
+/// Some branches of a push have not been pushed
+#[derive(Debug)]
+pub struct PushFailed {
+    pub failures: usize,
+}
+
+impl std::fmt::Display for PushFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} branches have not been pushed, run yggit push again to resume",
+            self.failures
+        )
+    }
+}
+
+impl std::error::Error for PushFailed {}
+
+/// How much of a report is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Only the branches that have changed or failed
+    Quiet,
+    #[default]
+    Normal,
+    /// Every branch, with the complete commits
+    Verbose,
+}
+
+/// Outcome of each branch of a push, in the order they have been handled
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PushReport {
+    pub pushed: Vec<BranchOutcome>,
+    /// Origins fetched before checking the leases
+    pub fetched: Vec<Origin>,
+}
+
+impl PushReport {
+    /// Returns an error if some branches have not been pushed
+    pub fn check(&self) -> Result<()> {
+        let failures = self
+            .pushed
+            .iter()
+            .filter(|outcome| matches!(outcome.status, BranchStatus::Failed(_)))
+            .count();
+        if failures == 0 {
+            return Ok(());
+        }
+        Err(PushFailed { failures }.into())
+    }
+
+    /// Render the outcome of each branch, one per line
+    pub fn render(&self, verbosity: Verbosity) -> String {
+        let short = |oid: &Oid| match verbosity {
+            Verbosity::Verbose => oid.to_string(),
+            _ => oid.to_string()[..7].to_string(),
+        };
+        let mut output = String::new();
+        if verbosity == Verbosity::Verbose {
+            for origin in &self.fetched {
+                output = format!("{output}fetched {origin}\n");
+            }
+        }
+        for outcome in &self.pushed {
+            let BranchOutcome {
+                origin,
+                branch,
+                old,
+                new,
+                status,
+            } = outcome;
+            let unchanged = match status {
+                BranchStatus::UpToDate
+                | BranchStatus::AlreadyPushed
+                | BranchStatus::NothingToDelete => true,
+                BranchStatus::NotPushed => *old == Some(*new),
+                _ => false,
+            };
+            if unchanged && verbosity == Verbosity::Quiet {
+                continue;
+            }
+            let range = match old {
+                Some(old) => format!("{}..{}", short(old), short(new)),
+                None => short(new),
+            };
+            let line = match status {
+                BranchStatus::New => format!("{origin}:{branch} created ({range})"),
+                BranchStatus::Pushed => format!("{origin}:{branch} pushed ({range})"),
+                BranchStatus::UpToDate if verbosity == Verbosity::Verbose => {
+                    format!("{origin}:{branch} up to date ({range})")
+                }
+                BranchStatus::UpToDate => format!("{origin}:{branch} up to date ({})", short(new)),
+                BranchStatus::Failed(reason) => format!("{origin}:{branch} not pushed: {reason}"),
+                BranchStatus::NotPushed => format!("{branch} moved ({range})"),
+                BranchStatus::AlreadyPushed => {
+                    format!("{origin}:{branch} already pushed ({})", short(new))
+                }
+                BranchStatus::Deleted => format!("{origin}:{branch} deleted"),
+                BranchStatus::NothingToDelete => {
+                    format!("{origin}:{branch} does not exist, nothing to delete")
+                }
+            };
+            output = format!("{output}{line}\n");
+        }
+        output
+    }
+}
+
+impl std::fmt::Display for PushReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(Verbosity::Normal))
+    }
+}
+
+/// Number of branches pushed at the same time
+const PUSH_WORKERS: usize = 4;
+
+/// Status of a branch from the outcome of its push
+fn pushed_status(result: Result<PushOutcome>) -> BranchStatus {
+    match result {
+        Err(error) => BranchStatus::Failed(format!("{error:#}")),
+        Ok(PushOutcome::New) => BranchStatus::New,
+        Ok(PushOutcome::Update) => BranchStatus::Pushed,
+        Ok(PushOutcome::UpToDate) => BranchStatus::UpToDate,
+        // The lease refuses to overwrite a diverged branch
+        Ok(PushOutcome::Diverged) => BranchStatus::Failed("remote has diverged".to_string()),
+    }
+}
+
+/// Add the outcome to the report, and save the progress if the branch has been pushed
+fn record_outcome(
+    git_dir: &Path,
+    progress: &mut Progress,
+    report: &mut PushReport,
+    outcome: BranchOutcome,
+) -> Result<()> {
+    if !matches!(outcome.status, BranchStatus::Failed(_)) {
+        progress
+            .pushed
+            .push((outcome.origin.clone(), outcome.branch.clone()));
+        progress.save(git_dir)?;
+    }
+    report.pushed.push(outcome);
+    Ok(())
+}
+
+/// Push the branches of each origin in a single atomic push, in the order of the origins in the stack
+///
+/// Returns the branches of the origins that do not support atomic pushes, they have not been pushed
+fn push_atomically(
+    git: &Git,
+    branches: Vec<(Origin, BranchName, PushStrategy, Oid)>,
+    progress: &mut Progress,
+    report: &mut PushReport,
+) -> Result<Vec<(Origin, BranchName, PushStrategy, Oid)>> {
+    let mut origins: Vec<Origin> = Vec::default();
+    for (origin, _, _, _) in &branches {
+        if !origins.contains(origin) {
+            origins.push(origin.clone());
+        }
+    }
+    let mut remaining = Vec::default();
+    for origin in origins {
+        let group: Vec<_> = branches
+            .iter()
+            .filter(|(branch_origin, _, _, _)| *branch_origin == origin)
+            .cloned()
+            .collect();
+        let olds: Vec<Option<Oid>> = group
+            .iter()
+            .map(|(_, branch, _, _)| git.remote_branch_oid(&origin, branch))
+            .collect();
+        let refs: Vec<(BranchName, PushStrategy)> = group
+            .iter()
+            .map(|(_, branch, strategy, _)| (branch.clone(), *strategy))
+            .collect();
+        let Some(results) = git.push_atomic(&origin, &refs)? else {
+            eprintln!("warning: {origin} does not support atomic pushes, its branches are pushed one by one");
+            remaining.extend(group);
+            continue;
+        };
+        for (((origin, branch, _, new), old), result) in group.into_iter().zip(olds).zip(results) {
+            let outcome = BranchOutcome {
+                origin,
+                branch,
+                old,
+                new,
+                status: pushed_status(result),
+            };
+            record_outcome(git.git_dir(), progress, report, outcome)?;
+        }
+    }
+    Ok(remaining)
+}
+
+/// Delete the conflicting references of the remote after confirmation, then push their branches again
+///
+/// Each conflict is the index of the rejected branch in the report, the conflicting reference and the strategy.
+/// A branch whose reference is kept stays failed
+fn resolve_ref_type_conflicts<F>(
+    git: &Git,
+    conflicts: Vec<(usize, String, PushStrategy)>,
+    max_retries: u32,
+    progress: &mut Progress,
+    report: &mut PushReport,
+    mut confirm: F,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    for (index, refname, strategy) in conflicts {
+        let BranchOutcome { origin, branch, .. } = &report.pushed[index];
+        let question =
+            format!("{refname} already exists on {origin}, delete it to push the branch {branch}?");
+        match confirm(&question) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(error) => {
+                eprintln!("warning: {error:#}, {refname} is kept");
+                continue;
+            }
+        }
+        git.delete_remote_ref(origin, &refname)?;
+        let result = push_branch(git, origin, branch, strategy, max_retries);
+        let outcome = BranchOutcome {
+            status: pushed_status(result),
+            ..report.pushed[index].clone()
+        };
+        if !matches!(outcome.status, BranchStatus::Failed(_)) {
+            progress
+                .pushed
+                .push((outcome.origin.clone(), outcome.branch.clone()));
+            progress.save(git.git_dir())?;
+        }
+        report.pushed[index] = outcome;
+    }
+    Ok(())
+}
+
+/// Push the branches that have not been pushed yet
+///
+/// The branches are pushed by PUSH_WORKERS threads, each of them opening the repository again.
+/// A branch failing to be pushed does not stop the others, the failures are part of the report.
+/// The progress is saved after each branch, and removed once everything is pushed
+pub fn resume_push(git: &Git, progress: Progress, max_retries: u32) -> Result<PushReport> {
+    resume_push_with(git, progress, max_retries, confirm_destructive)
+}
+
+/// Same as `resume_push`, the deletion of a reference conflicting with a branch is confirmed with the given function
+///
+/// The workers never ask anything, the conflicts are resolved once they are done
+fn resume_push_with<F>(
+    git: &Git,
+    mut progress: Progress,
+    max_retries: u32,
+    confirm: F,
+) -> Result<PushReport>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    let git_dir = git.git_dir();
+    progress.save(git_dir)?;
+
     let commits = git.list_commits()?;
-    // Push everything
-    for commit in &commits {
-        let EnhancedCommit {
-            note:
-                Some(Note {
-                    push: Some(Push { origin, branch }),
-                    ..
-                }),
-            ..
-        } = commit
-        else {
+    // The strategy of the push command, unless the branch has its own
+    let default_strategy = if progress.force {
+        PushStrategy::Force
+    } else {
+        PushStrategy::Lease
+    };
+    // The origin of the push command, unless the branch has its own
+    let default_origin = progress
+        .origin
+        .clone()
+        .unwrap_or(Origin::from(git.config.yggit.default_upstream.as_str()));
+    let mut branches: Vec<(Origin, BranchName, PushStrategy, Oid)> = Vec::default();
+    // The branches in the order of the stack, the report is sorted back to it
+    let mut order: Vec<(Origin, BranchName)> = Vec::default();
+    let mut report = PushReport::default();
+    for (
+        Push {
+            origin,
+            branch,
+            strategy,
+        },
+        id,
+    ) in branches_to_move(&commits)
+    {
+        if !is_selected(&progress.only, branch) {
+            continue;
+        }
+
+        let origin = origin.clone().unwrap_or(default_origin.clone());
+        order.push((origin.clone(), branch.clone()));
+
+        if progress.is_pushed(&origin, branch) {
+            report.pushed.push(BranchOutcome {
+                old: None,
+                new: id,
+                status: BranchStatus::AlreadyPushed,
+                origin,
+                branch: branch.clone(),
+            });
             continue;
+        }
+        branches.push((
+            origin,
+            branch.clone(),
+            strategy.unwrap_or(default_strategy),
+            id,
+        ));
+    }
+
+    // The lease is checked against the remote-tracking branch, it has to be recent
+    if progress.fetch {
+        for (origin, _, strategy, _) in &branches {
+            if *strategy == PushStrategy::Lease && !report.fetched.contains(origin) {
+                git.fetch(origin)?;
+                report.fetched.push(origin.clone());
+            }
+        }
+    }
+
+    // The workers only push the branches of the origins that cannot push atomically
+    if progress.atomic {
+        branches = push_atomically(git, branches, &mut progress, &mut report)?;
+    }
+
+    let work_tree = git.work_tree();
+    let mut conflicts = Vec::default();
+    let queue = Mutex::new(branches.into_iter());
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| -> Result<()> {
+        for _ in 0..PUSH_WORKERS {
+            let sender = sender.clone();
+            let queue = &queue;
+            scope.spawn(move || {
+                let worker = Git::reopen(git_dir, work_tree);
+                loop {
+                    // A worker that panicked does not stop the others from taking the next branches
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((origin, branch, strategy, new)) = next else {
+                        break;
+                    };
+                    let (old, result) = match &worker {
+                        Ok(worker) => (
+                            worker.remote_branch_oid(&origin, &branch),
+                            // A panic fails the branch, instead of the whole push
+                            std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                push_branch(worker, &origin, &branch, strategy, max_retries)
+                            }))
+                            .unwrap_or_else(|_| {
+                                Err(anyhow::Error::msg("the push of the branch panicked"))
+                            }),
+                        ),
+                        Err(error) => (None, Err(anyhow::Error::msg(format!("{error:#}")))),
+                    };
+                    // Some servers refuse a branch with the name of a tag
+                    let conflict = match &result {
+                        Err(error) => error
+                            .downcast_ref::<RefTypeConflict>()
+                            .map(|conflict| (conflict.refname.clone(), strategy)),
+                        Ok(_) => None,
+                    };
+                    let outcome = BranchOutcome {
+                        origin,
+                        branch,
+                        old,
+                        new,
+                        status: pushed_status(result),
+                    };
+                    if sender.send((outcome, conflict)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (outcome, conflict) in receiver {
+            if let Some((refname, strategy)) = conflict {
+                conflicts.push((report.pushed.len(), refname, strategy));
+            }
+            record_outcome(git_dir, &mut progress, &mut report, outcome)?;
+        }
+        Ok(())
+    })?;
+    resolve_ref_type_conflicts(
+        git,
+        conflicts,
+        max_retries,
+        &mut progress,
+        &mut report,
+        confirm,
+    )?;
+
+    // The workers finish in any order
+    report.pushed.sort_by_key(|outcome| {
+        order
+            .iter()
+            .position(|(origin, branch)| *origin == outcome.origin && *branch == outcome.branch)
+    });
+
+    // The progress is kept to push the failed branches again
+    if report.check().is_ok() {
+        Progress::clear(git_dir)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply, branches_to_move, clean_branches, confirm_destructive_with, cut_at_scissors,
+        get_commit_meta, get_commit_meta_or_default, is_transient, meta_keys, migrate_note,
+        pending_rewords, push_from_notes, push_plan, read_all_meta, rebase, refuse_if_in_progress,
+        render_cached, reorder, reordered, reset_notes, restrict_to_branches, resume_push_with,
+        retry_transient, run_tests, save_note, set_commit_meta, try_get_commit_meta,
+        validate_branch_name, validate_todo, BranchOutcome, BranchStatus, DuplicateBranch,
+        InvalidBranchName, Note, Progress, Push, PushFailed, PushReport, PushStrategy, Reword,
+        TestFailed, Verbosity, SCISSORS, TEST_KEY,
+    };
+    use crate::{
+        git::{
+            tmp::{git, GitTmp},
+            BranchName, EnhancedCommit, Git, Origin, PushOutcome,
+        },
+        parser::{Commit, TagTarget, Target},
+    };
+    use git2::Oid;
+    use serde::{Deserialize, Serialize};
+    use tempfile::TempDir;
+
+    fn commit(id: &str, branch: Option<&str>) -> EnhancedCommit<Note> {
+        EnhancedCommit {
+            id: Oid::from_str(id).unwrap(),
+            parents: Vec::default(),
+            title: "title".to_string(),
+            description: None,
+            note: Some(Note {
+                pushes: branch
+                    .map(|branch| Push {
+                        origin: None,
+                        branch: branch.into(),
+                        strategy: None,
+                    })
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_branches_are_moved_from_base_to_tip() {
+        let commits = vec![
+            commit("01", Some("base")),
+            commit("02", None),
+            commit("03", Some("middle")),
+            commit("04", Some("tip")),
+        ];
+        let moves = branches_to_move(&commits);
+        let moves: Vec<(&str, Oid)> = moves
+            .into_iter()
+            .map(|(push, oid)| (push.branch.as_str(), oid))
+            .collect();
+        assert_eq!(
+            moves,
+            vec![
+                ("base", Oid::from_str("01").unwrap()),
+                ("middle", Oid::from_str("03").unwrap()),
+                ("tip", Oid::from_str("04").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pending_rewords() {
+        let commits = vec![commit("01", None), commit("02", None), commit("03", None)];
+        let edited = |id: &str, title: &str| Commit {
+            hash: Oid::from_str(id).unwrap(),
+            title: title.to_string(),
+            targets: Vec::default(),
+            tag: None,
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: None,
         };
+        let edited = vec![
+            edited("01", "title"),
+            edited("02", "better title"),
+            edited("03", "title"),
+        ];
+        assert_eq!(
+            pending_rewords(&commits, &edited),
+            vec![Reword {
+                id: Oid::from_str("02").unwrap(),
+                old: "title".to_string(),
+                new: "better title".to_string(),
+            }]
+        );
+    }
 
-        let origin = origin
-            .clone()
-            .unwrap_or(git.config.yggit.default_upstream.clone());
+    #[test]
+    fn test_note_pushes_format() {
+        // Notes written with a single branch per commit
+        let note: Note =
+            serde_json::from_str(r#"{"push":{"origin":null,"branch":"feature"}}"#).unwrap();
+        assert_eq!(note.pushes.len(), 1);
+        assert_eq!(
+            serde_json::to_string(&note).unwrap(),
+            r#"{"push":{"origin":null,"branch":"feature"}}"#
+        );
+        let note: Note = serde_json::from_str(r#"{"push":null}"#).unwrap();
+        assert!(note.pushes.is_empty());
+        assert_eq!(serde_json::to_string(&note).unwrap(), r#"{"push":null}"#);
 
-        if force {
-            git.push_force(&origin, branch)?;
-        } else {
-            // default case
-            git.push_force_with_lease(&origin, branch)?;
+        let json = r#"{"push":[{"origin":null,"branch":"feature"},{"origin":"upstream","branch":"release"}]}"#;
+        let note: Note = serde_json::from_str(json).unwrap();
+        let branches: Vec<&str> = note
+            .pushes
+            .iter()
+            .map(|push| push.branch.as_str())
+            .collect();
+        assert_eq!(branches, vec!["feature", "release"]);
+        assert_eq!(serde_json::to_string(&note).unwrap(), json);
+    }
+
+    #[test]
+    fn test_resume_interrupted_push() {
+        let git_dir = TempDir::new().expect("should be created");
+        assert_eq!(Progress::load(git_dir.path()).unwrap(), None);
+
+        // The push has been interrupted after the first branch
+        let progress = Progress {
+            force: false,
+            pushed: vec![(Origin::from("origin"), BranchName::from("first"))],
+            only: Vec::default(),
+            fetch: false,
+            origin: None,
+            atomic: false,
+        };
+        progress.save(git_dir.path()).expect("should be saved");
+
+        let progress = Progress::load(git_dir.path())
+            .unwrap()
+            .expect("the push should be resumable");
+        assert!(progress.is_pushed(&Origin::from("origin"), &BranchName::from("first")));
+        assert!(!progress.is_pushed(&Origin::from("origin"), &BranchName::from("second")));
+        assert!(!progress.is_pushed(&Origin::from("upstream"), &BranchName::from("first")));
+
+        Progress::clear(git_dir.path()).expect("should be cleared");
+        assert_eq!(Progress::load(git_dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_render_cached() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let git = Git::open(&repo.path()).unwrap();
+
+        let renders = std::cell::Cell::new(0);
+        let render = || {
+            renders.set(renders.get() + 1);
+            Ok(format!("render {}", renders.get()))
+        };
+
+        assert_eq!(render_cached(&git, "all", render).unwrap(), "render 1");
+        // Same state, the cache is hit
+        assert_eq!(render_cached(&git, "all", render).unwrap(), "render 1");
+        assert_eq!(renders.get(), 1);
+
+        // Another variant is rendered again
+        assert_eq!(render_cached(&git, "graph", render).unwrap(), "render 2");
+
+        // The notes have moved
+        repo.add_note(oid, &"my super note".to_string());
+        assert_eq!(render_cached(&git, "graph", render).unwrap(), "render 3");
+        assert_eq!(render_cached(&git, "graph", render).unwrap(), "render 3");
+        assert_eq!(renders.get(), 3);
+    }
+
+    #[test]
+    fn test_commit_meta() {
+        #[derive(Deserialize, Serialize, Debug, PartialEq)]
+        struct Review {
+            reviewers: Vec<String>,
+        }
+
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let git = Git::open(&repo.path()).unwrap();
+
+        let review = Review {
+            reviewers: vec!["yoda".to_string()],
+        };
+        set_commit_meta(&git, oid, "review", &review).expect("metadata should be written");
+        assert_eq!(
+            get_commit_meta::<Review>(&git, oid, "review").unwrap(),
+            Some(review)
+        );
+        assert!(get_commit_meta::<Review>(&git, oid, "other")
+            .unwrap()
+            .is_none());
+
+        // yggit keys cannot be used
+        assert!(set_commit_meta(&git, oid, "push", &"value").is_err());
+        // The test command is only written from the todo
+        let error = set_commit_meta(&git, oid, TEST_KEY, &"rm -rf /").unwrap_err();
+        assert_eq!(error.to_string(), "test is reserved by yggit");
+        assert!(git
+            .find_note::<Note>(oid)
+            .unwrap()
+            .unwrap()
+            .test_command()
+            .is_none());
+
+        // Editing the branch keeps the metadata
+        let commit = Commit {
+            hash: oid,
+            title: "first commit".to_string(),
+            targets: vec![Target {
+                origin: None,
+                branch: "feature".into(),
+                strategy: None,
+            }],
+            tag: None,
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: Some("cargo test".to_string()),
+        };
+        save_note(&git, vec![commit]).expect("note should be saved");
+        let review = get_commit_meta::<Review>(&git, oid, "review").unwrap();
+        assert_eq!(review.unwrap().reviewers, vec!["yoda".to_string()]);
+        let note = git.find_note::<Note>(oid).unwrap().unwrap();
+        assert_eq!(note.pushes[0].branch.as_str(), "feature");
+        assert_eq!(note.test_command(), Some("cargo test"));
+    }
+
+    #[test]
+    fn test_all_meta() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(meta_keys(&git, oid).unwrap().is_empty());
+
+        repo.add_note(
+            oid,
+            &serde_json::json!({"push": {"origin": null, "branch": "feature"}, "review": 2}),
+        );
+        // The notes read before are cached
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(meta_keys(&git, oid).unwrap(), vec!["push", "review"]);
+        let meta = read_all_meta(&git, oid).unwrap();
+        assert_eq!(meta["review"], serde_json::json!(2));
+    }
+
+    #[test]
+    fn test_commit_meta_helpers() {
+        #[derive(Deserialize, Serialize, Debug, PartialEq, Default)]
+        struct Review {
+            reviewers: Vec<String>,
         }
+
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let git = Git::open(&repo.path()).unwrap();
+
+        // Absent
+        assert_eq!(
+            get_commit_meta_or_default::<Review>(&git, oid, "review").unwrap(),
+            Review::default()
+        );
+        assert_eq!(try_get_commit_meta::<Review>(&git, oid, "review"), None);
+
+        // Present
+        let review = Review {
+            reviewers: vec!["yoda".to_string()],
+        };
+        set_commit_meta(&git, oid, "review", &review).unwrap();
+        assert_eq!(
+            get_commit_meta_or_default::<Review>(&git, oid, "review").unwrap(),
+            review
+        );
+        assert_eq!(
+            try_get_commit_meta::<Review>(&git, oid, "review"),
+            Some(review)
+        );
+
+        // Malformed
+        set_commit_meta(&git, oid, "review", &"not a review").unwrap();
+        assert!(get_commit_meta_or_default::<Review>(&git, oid, "review").is_err());
+        assert_eq!(try_get_commit_meta::<Review>(&git, oid, "review"), None);
+    }
+
+    #[test]
+    fn test_only_selected_branches_are_pushed() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        let [foo, bar] = ["foo", "bar"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+        let git = Git::open(&repo.path()).unwrap();
+
+        let commit = |hash: Oid, branch: &str| Commit {
+            hash,
+            title: branch.to_string(),
+            targets: vec![Target {
+                origin: None,
+                branch: branch.into(),
+                strategy: None,
+            }],
+            tag: None,
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: None,
+        };
+        let only = vec![BranchName::from("foo")];
+        let commits =
+            restrict_to_branches(&git, vec![commit(foo, "foo"), commit(bar, "bar")], &only)
+                .unwrap();
+        save_note(&git, commits).unwrap();
+        apply(&git, false, &only).unwrap();
+        push_from_notes(&git, false, 0, only, false, None, false).unwrap();
+
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(remote.contains("refs/heads/foo"));
+        assert!(!remote.contains("refs/heads/bar"));
+        let branches = git!(repo, "branch", "--list", "foo", "bar");
+        assert_eq!(branches.trim(), "foo");
+        assert!(git.find_note::<Note>(bar).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_failed_branch_does_not_stop_the_others() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        let names = ["one", "two", "three", "four", "five", "six"];
+        let commits = names
+            .iter()
+            .map(|name| {
+                repo.new_file(name, name);
+                repo.add_all();
+                Commit {
+                    hash: repo.commit(name),
+                    title: name.to_string(),
+                    targets: vec![Target {
+                        origin: None,
+                        branch: (*name).into(),
+                        strategy: None,
+                    }],
+                    tag: None,
+                    comments: Vec::default(),
+                    deletions: Vec::default(),
+                    test: None,
+                }
+            })
+            .collect();
+        let git = Git::open(&repo.path()).unwrap();
+        save_note(&git, commits).unwrap();
+        apply(&git, false, &[]).unwrap();
+        repo.lock_bare_ref("refs/heads/three");
+
+        let report = push_from_notes(&git, false, 0, Vec::default(), false, None, false).unwrap();
+        assert!(report.check().is_err());
+
+        let remote = git!(repo, "ls-remote", "origin");
+        for name in names {
+            let pushed = remote.contains(&format!("refs/heads/{name}"));
+            assert_eq!(pushed, name != "three", "{name}");
+            let outcome = report
+                .pushed
+                .iter()
+                .find(|outcome| outcome.branch.as_str() == name)
+                .unwrap();
+            assert_eq!(
+                matches!(outcome.status, BranchStatus::Failed(_)),
+                name == "three",
+                "{name}"
+            );
+        }
+        let progress = Progress::load(git.git_dir()).unwrap().unwrap();
+        assert_eq!(progress.pushed.len(), names.len() - 1);
+
+        // The report follows the stack, whatever the order the workers finished in
+        let branches: Vec<&str> = report
+            .pushed
+            .iter()
+            .map(|outcome| outcome.branch.as_str())
+            .collect();
+        assert_eq!(branches, names);
+    }
+
+    #[test]
+    fn test_atomic_push() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        let names = ["one", "two", "three"];
+        let commits = names
+            .iter()
+            .map(|name| {
+                repo.new_file(name, name);
+                repo.add_all();
+                Commit {
+                    hash: repo.commit(name),
+                    title: name.to_string(),
+                    targets: vec![Target {
+                        origin: None,
+                        branch: (*name).into(),
+                        strategy: None,
+                    }],
+                    tag: None,
+                    comments: Vec::default(),
+                    deletions: Vec::default(),
+                    test: None,
+                }
+            })
+            .collect();
+        let git = Git::open(&repo.path()).unwrap();
+        save_note(&git, commits).unwrap();
+        apply(&git, false, &[]).unwrap();
+        repo.lock_bare_ref("refs/heads/two");
+
+        // The remote cannot update two, so none of the branches is pushed
+        let report = push_from_notes(&git, false, 0, Vec::default(), false, None, true).unwrap();
+        assert!(report
+            .pushed
+            .iter()
+            .all(|outcome| matches!(outcome.status, BranchStatus::Failed(_))));
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/*");
+        assert!(!remote.contains("refs/heads/one"));
+        assert!(!remote.contains("refs/heads/three"));
+
+        // Without atomic pushes, the branches are pushed one by one
+        repo.bare_config("receive.advertiseAtomic", "false");
+        let report = push_from_notes(&git, false, 0, Vec::default(), false, None, true).unwrap();
+        assert_eq!(
+            report.check().unwrap_err().to_string(),
+            PushFailed { failures: 1 }.to_string()
+        );
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/*");
+        assert!(remote.contains("refs/heads/one"));
+        assert!(remote.contains("refs/heads/three"));
+    }
+
+    #[test]
+    fn test_ref_type_conflict() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        git!(repo, "tag", "feature");
+        git!(repo, "push", "origin", "refs/tags/feature");
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+        let git = Git::open(&repo.path()).unwrap();
+        let commit = Commit {
+            hash: head,
+            title: "feature commit".to_string(),
+            targets: vec![Target {
+                origin: None,
+                branch: "feature".into(),
+                strategy: None,
+            }],
+            tag: None,
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: None,
+        };
+        save_note(&git, vec![commit]).unwrap();
+        apply(&git, false, &[]).unwrap();
+        // The remote refuses the branch while the tag exists
+        repo.lock_bare_ref("refs/heads/feature");
+        let lock = repo
+            .bare
+            .as_ref()
+            .unwrap()
+            .path()
+            .join("refs/heads/feature.lock");
+
+        // The tag is kept when the deletion is declined
+        let report = resume_push_with(&git, Progress::default(), 0, |_| Ok(false)).unwrap();
+        assert!(matches!(
+            report.pushed[0].status,
+            BranchStatus::Failed(ref reason) if reason.contains("refs/tags/feature already exists")
+        ));
+        let remote = git!(repo, "ls-remote", "origin", "refs/tags/feature");
+        assert!(!remote.is_empty());
+
+        let mut questions = Vec::default();
+        let report = resume_push_with(&git, Progress::default(), 0, |question| {
+            questions.push(question.to_string());
+            std::fs::remove_file(&lock).unwrap();
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(
+            questions,
+            vec![
+                "refs/tags/feature already exists on origin, delete it to push the branch feature?"
+            ]
+        );
+        assert_eq!(report.pushed[0].status, BranchStatus::New);
+        report.check().expect("the branch should be pushed");
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(remote.contains("refs/heads/feature"));
+        assert!(!remote.contains("refs/tags/feature"));
+    }
+
+    #[test]
+    fn test_push_report() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("foo", "foo");
+        repo.add_all();
+        let foo = repo.commit("foo");
+        let git = Git::open(&repo.path()).unwrap();
+        let commit = Commit {
+            hash: foo,
+            title: "foo".to_string(),
+            targets: vec![Target {
+                origin: None,
+                branch: "foo".into(),
+                strategy: None,
+            }],
+            tag: None,
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: None,
+        };
+        save_note(&git, vec![commit]).unwrap();
+
+        let report = apply(&git, false, &[]).unwrap();
+        assert_eq!(report.pushed.len(), 1);
+        assert_eq!(report.pushed[0].old, None);
+        assert_eq!(report.pushed[0].status, BranchStatus::NotPushed);
+
+        let report = push_from_notes(&git, false, 0, Vec::default(), false, None, false).unwrap();
+        assert_eq!(report.pushed[0].status, BranchStatus::New);
+        assert_eq!(report.pushed[0].new, foo);
+        let report = push_from_notes(&git, false, 0, Vec::default(), false, None, false).unwrap();
+        assert_eq!(report.pushed[0].status, BranchStatus::UpToDate);
+        assert_eq!(report.pushed[0].old, Some(foo));
+        assert!(report.check().is_ok());
+        assert_eq!(
+            report.to_string(),
+            format!("origin:foo up to date ({})\n", &foo.to_string()[..7])
+        );
+    }
+
+    #[test]
+    fn test_push_to_default_origin() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("foo", "foo");
+        repo.add_all();
+        let foo = repo.commit("foo");
+        let fork = TempDir::new().unwrap();
+        git!(repo, "init", "--bare", fork.path().to_str().unwrap());
+        git!(repo, "remote", "add", "fork", fork.path().to_str().unwrap());
+        repo.add_note(
+            foo,
+            &Note {
+                pushes: vec![
+                    Push {
+                        origin: None,
+                        branch: "feature".into(),
+                        strategy: None,
+                    },
+                    Push {
+                        origin: Some(Origin::from("origin")),
+                        branch: "shared".into(),
+                        strategy: None,
+                    },
+                ],
+                ..Default::default()
+            },
+        );
+
+        // The origin of the todo wins over the default one
+        let git = Git::open(&repo.path()).unwrap();
+        apply(&git, false, &[]).unwrap();
+        let fork_origin = Some(Origin::from("fork"));
+        let report =
+            push_from_notes(&git, false, 0, Vec::default(), false, fork_origin, false).unwrap();
+        assert!(report.check().is_ok());
+        let remote = git!(repo, "ls-remote", "fork");
+        assert!(remote.contains("refs/heads/feature"));
+        assert!(!remote.contains("refs/heads/shared"));
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(remote.contains("refs/heads/shared"));
+        assert!(!remote.contains("refs/heads/feature"));
+    }
+
+    #[test]
+    fn test_confirm_destructive() {
+        let answer = |input: &str, is_terminal| {
+            confirm_destructive_with("Delete?", is_terminal, &mut input.as_bytes())
+        };
+        // Nothing is deleted without an explicit yes from a terminal
+        assert!(answer("y\n", false).is_err());
+        assert!(!answer("", true).unwrap());
+        assert!(!answer("\n", true).unwrap());
+        assert!(!answer("n\n", true).unwrap());
+        assert!(answer("y\n", true).unwrap());
+        assert!(answer("Yes\n", true).unwrap());
+    }
+
+    #[test]
+    fn test_cut_at_scissors() {
+        let help = format!("{SCISSORS}\n# help\n");
+        let text = format!("pick\n# comment\n{help}");
+        assert_eq!(cut_at_scissors(&text), ("pick\n# comment\n", help.as_str()));
+        assert_eq!(cut_at_scissors("pick\n"), ("pick\n", ""));
+        // Only a whole line is a scissors line
+        let text = format!("# {SCISSORS}\n");
+        assert_eq!(cut_at_scissors(&text), (text.as_str(), ""));
+    }
+
+    #[test]
+    fn test_push_report_verbosity() {
+        let [old, new] = ["a", "b"].map(|hex| Oid::from_str(&hex.repeat(40)).unwrap());
+        let outcome = |branch: &str, old: Option<Oid>, status: BranchStatus| BranchOutcome {
+            origin: Origin::from("origin"),
+            branch: branch.into(),
+            old,
+            new,
+            status,
+        };
+        let synced = PushReport {
+            pushed: vec![
+                outcome("synced", Some(new), BranchStatus::UpToDate),
+                outcome("resumed", None, BranchStatus::AlreadyPushed),
+                outcome("gone", None, BranchStatus::NothingToDelete),
+            ],
+            fetched: vec![Origin::from("origin")],
+        };
+        // Nothing at all is printed for a synced stack
+        assert_eq!(synced.render(Verbosity::Quiet), "");
+        assert_eq!(
+            synced.render(Verbosity::Verbose),
+            format!(
+                "fetched origin\n\
+                 origin:synced up to date ({new}..{new})\n\
+                 origin:resumed already pushed ({new})\n\
+                 origin:gone does not exist, nothing to delete\n"
+            )
+        );
+
+        let report = PushReport {
+            pushed: vec![
+                outcome("created", None, BranchStatus::New),
+                outcome("synced", Some(new), BranchStatus::UpToDate),
+                outcome("moved", Some(old), BranchStatus::Pushed),
+                outcome(
+                    "failed",
+                    Some(old),
+                    BranchStatus::Failed("rejected".to_string()),
+                ),
+                outcome("removed", Some(old), BranchStatus::Deleted),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            report.render(Verbosity::Quiet),
+            "origin:created created (bbbbbbb)\n\
+             origin:moved pushed (aaaaaaa..bbbbbbb)\n\
+             origin:failed not pushed: rejected\n\
+             origin:removed deleted\n"
+        );
+        assert_eq!(report.render(Verbosity::Normal), report.to_string());
+        assert!(report
+            .render(Verbosity::Verbose)
+            .contains(&format!("origin:moved pushed ({old}..{new})\n")));
+    }
+
+    #[test]
+    fn test_push_plan() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        let [foo, bar] = ["foo", "bar"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+        git!(repo, "branch", "foo", &bar.to_string());
+        let git = Git::open(&repo.path()).unwrap();
+
+        let commit = |hash: Oid, branch: &str, tag: Option<&str>| Commit {
+            hash,
+            title: branch.to_string(),
+            targets: vec![Target {
+                origin: None,
+                branch: branch.into(),
+                strategy: None,
+            }],
+            tag: tag.map(|name| TagTarget {
+                name: name.to_string(),
+                message: None,
+            }),
+            comments: Vec::default(),
+            deletions: Vec::default(),
+            test: None,
+        };
+        let commits = vec![commit(foo, "foo", Some("v1")), commit(bar, "bar", None)];
+        let short = |oid: Oid| oid.to_string()[..7].to_string();
+        let plan = push_plan(&git, &commits, &[], false).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                format!(
+                    "would set branch foo to {} and push to origin (1 files changed, 0 insertions(+), 1 deletions(-))",
+                    short(foo)
+                ),
+                format!("would tag {} as v1", short(foo)),
+                format!("would create branch bar on {} and push to origin", short(bar)),
+            ]
+        );
+
+        let plan = push_plan(&git, &commits, &[BranchName::from("bar")], false).unwrap();
+        assert_eq!(plan.len(), 1);
+
+        // Someone else moved foo on the remote since it was fetched
+        git!(repo, "push", "origin", &format!("{bar}:refs/heads/foo"));
+        git!(repo, "update-ref", "refs/remotes/origin/foo", "main");
+        let plan = push_plan(&git, &commits, &[BranchName::from("foo")], false).unwrap();
+        assert!(plan[0].ends_with(", but origin:foo has diverged, the push would be refused"));
+        let plan = push_plan(&git, &commits, &[BranchName::from("foo")], true).unwrap();
+        assert!(!plan[0].contains("diverged"));
+
+        // Once fetched the lease holds, but a normal push would rewind the remote
+        git!(repo, "fetch");
+        let plan = push_plan(&git, &commits, &[BranchName::from("foo")], false).unwrap();
+        assert!(!plan[0].contains("diverged"));
+        let mut normal = vec![commit(foo, "foo", None)];
+        normal[0].targets[0].strategy = Some(PushStrategy::Normal);
+        let plan = push_plan(&git, &normal, &[], true).unwrap();
+        assert!(plan[0].ends_with(", but origin:foo has diverged, the push would be refused"));
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/foo");
+        assert!(remote.starts_with(&bar.to_string()));
+
+        // Nothing has been modified
+        assert_eq!(git.branch_commit(&BranchName::from("foo")), Some(bar));
+        assert_eq!(git.branch_commit(&BranchName::from("bar")), None);
+        assert!(git.find_note::<Note>(foo).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_clean_branches() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.create_branch("stack");
+        let [branch, tagged, other] = ["branch", "tagged", "other"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+        repo.add_note(
+            branch,
+            &serde_json::json!({"push": {"origin": null, "branch": "a"}}),
+        );
+        repo.add_note(
+            tagged,
+            &serde_json::json!({
+                "push": {"origin": null, "branch": "b"},
+                "tag": {"name": "v1", "message": null},
+                "review": 2
+            }),
+        );
+        repo.add_note(other, &serde_json::json!({"review": 3}));
+
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(clean_branches(&git).unwrap(), 2);
+
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(git.find_note::<Note>(branch).unwrap().is_none());
+        let note = git.find_note::<Note>(tagged).unwrap().unwrap();
+        assert!(note.pushes.is_empty());
+        assert_eq!(note.tag.unwrap().name, "v1");
+        assert_eq!(note.meta["review"], serde_json::json!(2));
+        let note = git.find_note::<Note>(other).unwrap().unwrap();
+        assert_eq!(note.meta["review"], serde_json::json!(3));
+    }
+
+    #[test]
+    fn test_validate_branch_name() {
+        for name in [
+            "feature",
+            "feature/x",
+            "fix-1_2",
+            "user@feature",
+            "v1.2",
+            "a.b/c",
+        ] {
+            assert!(
+                validate_branch_name(&name.into()).is_ok(),
+                "{name} should be valid"
+            );
+        }
+        for name in [
+            "",
+            "@",
+            "HEAD",
+            "-feature",
+            "/feature",
+            "feature/",
+            "a//b",
+            "a..b",
+            "a@{1}",
+            "feature.",
+            "a/.hidden",
+            "feature.lock",
+            "with space",
+            "tab\there",
+            "what?",
+        ] {
+            assert!(
+                validate_branch_name(&name.into()).is_err(),
+                "{name} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_todo() {
+        let commits = crate::parser::instruction_from_string(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n-> feature/\n".to_string(),
+        )
+        .unwrap();
+        let error = validate_todo(&commits).unwrap_err();
+        let invalid = error
+            .downcast_ref::<InvalidBranchName>()
+            .expect("the branch is invalid");
+        assert_eq!(invalid.name, "feature/");
+    }
+
+    #[test]
+    fn test_duplicate_branch() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature/x\n",
+            "-> upstream:feature/x\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            "-> feature/x\n",
+        );
+        let commits = crate::parser::instruction_from_string(input.to_string()).unwrap();
+        let error = validate_todo(&commits).unwrap_err();
+        let duplicate = error
+            .downcast_ref::<DuplicateBranch>()
+            .expect("the branch is set twice");
+        assert_eq!(duplicate.name.as_str(), "feature/x");
+        assert_eq!(duplicate.origin, None);
+        assert_eq!(duplicate.first, commits[0].hash);
+        assert_eq!(duplicate.second, commits[1].hash);
+
+        // The same branch on other origins is not a duplicate
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature/x\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            "-> upstream:feature/x\n",
+        );
+        let commits = crate::parser::instruction_from_string(input.to_string()).unwrap();
+        assert!(validate_todo(&commits).is_ok());
+    }
+
+    #[test]
+    fn test_reordered() {
+        let commits = vec![
+            commit("01", None),
+            commit("02", Some("feature")),
+            commit("03", None),
+        ];
+        let edited = |ids: &[&str]| -> Vec<Commit> {
+            ids.iter()
+                .map(|id| Commit {
+                    hash: Oid::from_str(id).unwrap(),
+                    title: "title".to_string(),
+                    targets: Vec::default(),
+                    tag: None,
+                    comments: Vec::default(),
+                    deletions: Vec::default(),
+                    test: None,
+                })
+                .collect()
+        };
+        assert_eq!(reordered(&commits, &edited(&["01", "02", "03"])), None);
+        assert_eq!(
+            reordered(&commits, &edited(&["02", "01", "03"])),
+            Some(
+                ["02", "01", "03"]
+                    .map(|id| Oid::from_str(id).unwrap())
+                    .to_vec()
+            )
+        );
+        // Only a pure reordering is applied
+        assert_eq!(reordered(&commits, &edited(&["02", "01"])), None);
+        assert_eq!(reordered(&commits, &edited(&["02", "01", "01"])), None);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.create_branch("stack");
+        let [first, second] = ["first", "second"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+        repo.add_note(
+            first,
+            &serde_json::json!({"push": {"origin": null, "branch": "feature"}}),
+        );
+
+        let git = Git::open(&repo.path()).unwrap();
+        reorder(&git, &[second, first]).expect("the commits should be reordered");
+        let git = Git::open(&repo.path()).unwrap();
+        let commits = git.list_commits::<Note>().unwrap();
+        let titles: Vec<&str> = commits.iter().map(|commit| commit.title.as_str()).collect();
+        assert_eq!(titles, vec!["second", "first"]);
+        // The note follows its commit
+        let pushes = &commits[1].note.as_ref().expect("the note is copied").pushes;
+        assert_eq!(pushes[0].branch.as_str(), "feature");
+        assert!(commits[0].note.is_none());
+
+        // Both commits change the same file
+        repo.new_file("first", "changed");
+        repo.add_all();
+        let third = repo.commit("third");
+        let head = git!(repo, "rev-parse", "HEAD");
+        let git = Git::open(&repo.path()).unwrap();
+        let error = reorder(&git, &[commits[0].id, third, commits[1].id]).unwrap_err();
+        assert!(error.to_string().contains("aborted"));
+        assert!(git.operation_in_progress().is_none());
+        assert_eq!(git!(repo, "rev-parse", "HEAD"), head);
+    }
+
+    #[test]
+    fn test_rebase_with_applied_commit() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.create_branch("stack");
+        let [first, second] = ["first", "second"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+        repo.add_note(
+            first,
+            &serde_json::json!({"push": {"origin": null, "branch": "upstreamed"}}),
+        );
+        repo.add_note(
+            second,
+            &serde_json::json!({"push": {"origin": null, "branch": "feature"}}),
+        );
+        git!(repo, "branch", "upstreamed", &first.to_string());
+        git!(repo, "branch", "feature", &second.to_string());
+        // The first commit has already been merged upstream
+        git!(repo, "checkout", "-b", "upstream", "main");
+        repo.new_file("upstream", "upstream");
+        repo.add_all();
+        repo.commit("upstream");
+        git!(repo, "cherry-pick", &first.to_string());
+        let upstream = Oid::from_str(git!(repo, "rev-parse", "HEAD").trim()).unwrap();
+        repo.checkout("stack");
+
+        let git = Git::open(&repo.path()).unwrap();
+        rebase(&git, "upstream").expect("the stack should be rebased");
+        let git = Git::open(&repo.path()).unwrap();
+        let commits = git.list_commits::<Note>().unwrap();
+        let titles: Vec<&str> = commits.iter().map(|commit| commit.title.as_str()).collect();
+        assert_eq!(titles, vec!["upstream", "first", "second"]);
+        assert_eq!(commits[1].id, upstream);
+        assert_eq!(
+            git.branch_commit(&BranchName::from("feature")),
+            Some(commits[2].id)
+        );
+        // The branch of the applied commit goes to the commit containing its change, with its note
+        assert_eq!(
+            git.branch_commit(&BranchName::from("upstreamed")),
+            Some(upstream)
+        );
+        let note = git.find_note::<Note>(upstream).unwrap().unwrap();
+        assert_eq!(note.pushes[0].branch.as_str(), "upstreamed");
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.create_branch("stack");
+        let [first, second] = ["first", "second"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(run_tests(&git).unwrap(), 0);
+
+        repo.add_note(first, &serde_json::json!({"test": "test -f first"}));
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(run_tests(&git).unwrap(), 1);
+        assert_eq!(git!(repo, "rev-parse", "HEAD").trim(), second.to_string());
+
+        repo.add_note(second, &serde_json::json!({"test": "test -f missing"}));
+        let git = Git::open(&repo.path()).unwrap();
+        let error = run_tests(&git).unwrap_err();
+        let failed = error.downcast_ref::<TestFailed>().expect("the test fails");
+        assert_eq!(failed.commit, second);
+        assert_eq!(failed.command, "test -f missing");
+        assert!(git.operation_in_progress().is_some());
+        git!(repo, "rebase", "--abort");
+    }
+
+    #[test]
+    fn test_migrate_note() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let old = repo.commit("first commit");
+        repo.add_note(
+            old,
+            &serde_json::json!({"push": {"origin": null, "branch": "feature"}}),
+        );
+        // Like an amend without notes.rewriteRef
+        git!(
+            repo,
+            "-c",
+            "notes.rewrite.amend=false",
+            "commit",
+            "--amend",
+            "-m",
+            "amended commit"
+        );
+        let new = Oid::from_str(git!(repo, "rev-parse", "HEAD").trim()).unwrap();
+
+        let git = Git::open(&repo.path()).unwrap();
+        migrate_note(&git, old, new, false).expect("the note should be moved");
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(git.find_note::<Note>(old).unwrap().is_none());
+        let note = git.find_note::<Note>(new).unwrap().unwrap();
+        assert_eq!(note.pushes[0].branch.as_str(), "feature");
+
+        // There is nothing left to move
+        assert!(migrate_note(&git, old, new, false).is_err());
+        repo.add_note(old, &serde_json::json!({"review": 2}));
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(migrate_note(&git, old, new, false).is_err());
+        migrate_note(&git, old, new, true).expect("the note is replaced with force");
+        let git = Git::open(&repo.path()).unwrap();
+        let note = git.find_note::<Note>(new).unwrap().unwrap();
+        assert_eq!(note.meta["review"], serde_json::json!(2));
+    }
+
+    #[test]
+    fn test_reset_notes() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.create_branch("stack");
+        let oids: Vec<Oid> = ["first", "second", "third"]
+            .iter()
+            .map(|name| {
+                repo.new_file(name, name);
+                repo.add_all();
+                repo.commit(name)
+            })
+            .collect();
+        let git = Git::open(&repo.path()).unwrap();
+
+        let target = |branch: &str| {
+            vec![Target {
+                origin: None,
+                branch: branch.into(),
+                strategy: None,
+            }]
+        };
+        let commits = vec![
+            Commit {
+                hash: oids[0],
+                title: "first".to_string(),
+                targets: target("first"),
+                tag: None,
+                comments: Vec::default(),
+                deletions: Vec::default(),
+                test: None,
+            },
+            Commit {
+                hash: oids[1],
+                title: "second".to_string(),
+                targets: Vec::default(),
+                tag: None,
+                comments: Vec::default(),
+                deletions: Vec::default(),
+                test: None,
+            },
+            Commit {
+                hash: oids[2],
+                title: "third".to_string(),
+                targets: target("third"),
+                tag: None,
+                comments: Vec::default(),
+                deletions: Vec::default(),
+                test: None,
+            },
+        ];
+        save_note(&git, commits).unwrap();
+        set_commit_meta(&git, oids[2], "review", &"approved").unwrap();
+
+        assert_eq!(reset_notes(&git).unwrap(), 2);
+        assert!(git.find_note::<Note>(oids[0]).unwrap().is_none());
+        assert!(git.find_note::<Note>(oids[1]).unwrap().is_none());
+        let note = git
+            .find_note::<Note>(oids[2])
+            .unwrap()
+            .expect("the metadata are kept");
+        assert!(note.pushes.is_empty());
+        assert_eq!(
+            get_commit_meta::<String>(&git, oids[2], "review").unwrap(),
+            Some("approved".to_string())
+        );
+    }
+
+    #[test]
+    fn test_refuse_if_in_progress() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(refuse_if_in_progress(&git).is_ok());
+
+        // A merge has been started
+        std::fs::write(git.git_dir().join("MERGE_HEAD"), format!("{}\n", oid)).unwrap();
+        assert_eq!(
+            refuse_if_in_progress(&git).unwrap_err().to_string(),
+            "merge in progress; finish or abort it first"
+        );
+    }
+
+    #[test]
+    fn test_retry_transient() {
+        let transient = || {
+            anyhow::Error::new(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Net,
+                "connection reset by peer",
+            ))
+            .context("cannot push origin:feature")
+        };
+        assert!(is_transient(&transient()));
+
+        // The remote fails once, then accepts the push
+        let mut attempts = 0;
+        let result = retry_transient(2, std::time::Duration::ZERO, || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(transient())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        // It gives up after max_retries
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry_transient(2, std::time::Duration::ZERO, || {
+            attempts += 1;
+            Err(transient())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // A rejection is not retried
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry_transient(2, std::time::Duration::ZERO, || {
+            attempts += 1;
+            Err(anyhow::Error::msg(
+                "remote rejected refs/heads/feature: locked",
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let auth = anyhow::Error::new(git2::Error::new(
+            git2::ErrorCode::Auth,
+            git2::ErrorClass::Http,
+            "authentication required",
+        ));
+        assert!(!is_transient(&auth));
+    }
+
+    #[test]
+    fn test_retry_push_failing_after_negotiation() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let head = repo.commit("first commit");
+        repo.break_bare_packs();
+
+        // The remote fails once, after accepting the push, then it is repaired
+        let git = Git::open(&repo.path()).unwrap();
+        let mut attempts = 0;
+        let outcome = retry_transient(2, std::time::Duration::ZERO, || {
+            attempts += 1;
+            if attempts == 2 {
+                repo.repair_bare_packs();
+            }
+            git.push_force_with_lease(&Origin::from("origin"), &BranchName::from("main"))
+        })
+        .expect("should be pushed once repaired");
+        assert_eq!(outcome, PushOutcome::New);
+        assert_eq!(attempts, 2);
+        let remote = git!(repo, "ls-remote", "origin", "refs/heads/main");
+        assert!(remote.starts_with(&head.to_string()));
     }
-    Ok(())
 }
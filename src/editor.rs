@@ -0,0 +1,233 @@
+use crate::git::Ui;
+use anyhow::{Context, Result};
+use std::{io::Read, process::Command};
+
+/// How the todo files are edited
+pub trait Editor {
+    /// Let the user edit the file and returns its new content
+    fn edit(&self, file_path: &str) -> Result<String>;
+
+    /// Returns true if the content is read from stdin, no question can be answered there then
+    fn reads_stdin(&self) -> bool {
+        false
+    }
+}
+
+/// Open the file with the editor of the user, in the terminal
+///
+/// The command is None when no editor is configured, editing fails then
+struct Terminal {
+    command: Option<String>,
+}
+
+/// Split the command of the editor into the program and its arguments, like `code --wait`
+///
+/// Words are separated by whitespaces, unless they are within single or double quotes
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::default();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::default).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::default);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::default).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// Characters only the shell understands, a command with one of them is run by the shell, like git does
+const SHELL_METACHARACTERS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '*', '?', '[', '#', '~', '=', '%',
+];
+
+/// Returns true if the command has to be run by the shell, like `sh -c 'vim "$1"' --`
+fn needs_shell(command: &str) -> bool {
+    command.contains(SHELL_METACHARACTERS)
+}
+
+impl Terminal {
+    /// Returns the program to run and its arguments, the file to edit is the last one
+    ///
+    /// A command needing the shell is run with `sh -c '<command> "$@"'`, or `cmd /C` on Windows
+    fn command_line(&self, file_path: &str) -> Result<(String, Vec<String>)> {
+        let command = self
+            .command
+            .as_deref()
+            .context("editor not found in configuration")?;
+        if needs_shell(command) {
+            let args = if cfg!(windows) {
+                vec!["/C".to_string(), format!("{} {}", command, file_path)]
+            } else {
+                vec![
+                    "-c".to_string(),
+                    format!("{} \"$@\"", command),
+                    command.to_string(),
+                    file_path.to_string(),
+                ]
+            };
+            let shell = if cfg!(windows) { "cmd" } else { "sh" };
+            return Ok((shell.to_string(), args));
+        }
+        let mut words = split_command(command);
+        if words.is_empty() {
+            return Err(anyhow::Error::msg("the editor command is empty"));
+        }
+        let program = words.remove(0);
+        words.push(file_path.to_string());
+        Ok((program, words))
+    }
+}
+
+impl Editor for Terminal {
+    fn edit(&self, file_path: &str) -> Result<String> {
+        let (program, args) = self.command_line(file_path)?;
+        let output = Command::new(program)
+            .args(args)
+            .status()
+            .context("Failed to open editor")?;
+        let true = output.success() else {
+            return Err(anyhow::Error::msg("Editor did not end successfully"));
+        };
+        std::fs::read_to_string(file_path).context("Cannot read string from editor")
+    }
+}
+
+/// Read the edited content from stdin, so that it can be piped
+struct Stdin;
+
+impl Editor for Stdin {
+    fn edit(&self, _file_path: &str) -> Result<String> {
+        let mut content = String::default();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Cannot read the content from stdin")?;
+        Ok(content)
+    }
+
+    fn reads_stdin(&self) -> bool {
+        true
+    }
+}
+
+/// Refuse to edit anything, for instance in the CI
+struct NoEditor;
+
+impl Editor for NoEditor {
+    fn edit(&self, file_path: &str) -> Result<String> {
+        Err(anyhow::Error::msg(format!(
+            "{file_path} has to be edited but yggit.ui is none"
+        )))
+    }
+}
+
+/// Returns the editor selected by yggit.ui
+///
+/// The terminal needs the command of the editor, from core.editor, VISUAL or EDITOR,
+/// it is only looked for when a file is edited
+pub fn from_config(ui: Ui, command: Option<&str>) -> Box<dyn Editor> {
+    match ui {
+        Ui::Terminal => Box::new(Terminal {
+            command: command.map(ToString::to_string),
+        }),
+        Ui::Stdin => Box::new(Stdin),
+        Ui::None => Box::new(NoEditor),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_config, needs_shell, Terminal};
+    use crate::git::Ui;
+
+    #[test]
+    fn test_editor_with_arguments() {
+        let terminal = Terminal {
+            command: Some("code --wait".to_string()),
+        };
+        let (program, args) = terminal.command_line("/tmp/todo.yggit").unwrap();
+        assert_eq!(program, "code");
+        assert_eq!(args, vec!["--wait", "/tmp/todo.yggit"]);
+
+        let terminal = Terminal {
+            command: Some("'/opt/my editor/bin' -c \"\" --title \"yggit todo\"".to_string()),
+        };
+        let (program, args) = terminal.command_line("todo").unwrap();
+        assert_eq!(program, "/opt/my editor/bin");
+        assert_eq!(args, vec!["-c", "", "--title", "yggit todo", "todo"]);
+
+        let terminal = Terminal {
+            command: Some("  ".to_string()),
+        };
+        assert!(terminal.command_line("todo").is_err());
+    }
+
+    #[test]
+    fn test_editor_through_the_shell() {
+        assert!(!needs_shell("code --wait"));
+        assert!(!needs_shell("'/opt/my editor/bin' --title \"yggit todo\""));
+        assert!(needs_shell("sh -c 'vim \"$1\"' --"));
+        assert!(needs_shell("cat | tee ~/todo"));
+
+        let terminal = Terminal {
+            command: Some("my-editor 2>/dev/null".to_string()),
+        };
+        let (program, args) = terminal.command_line("todo").unwrap();
+        assert_eq!(program, "sh");
+        assert_eq!(
+            args,
+            vec![
+                "-c",
+                "my-editor 2>/dev/null \"$@\"",
+                "my-editor 2>/dev/null",
+                "todo"
+            ]
+        );
+
+        // The file is given to the shell as its first argument
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let editor = from_config(Ui::Terminal, Some("sh -c 'echo \"$1\" > \"$1\"' --"));
+        assert_eq!(editor.edit(path).unwrap(), format!("{path}\n"));
+    }
+
+    #[test]
+    fn test_terminal_edits_the_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let editor = from_config(Ui::Terminal, Some("sh -c 'echo edited > \"$0\"'"));
+        assert_eq!(editor.edit(path).unwrap(), "edited\n");
+    }
+
+    #[test]
+    fn test_terminal_needs_an_editor() {
+        let editor = from_config(Ui::Terminal, None);
+        let Err(error) = editor.edit("/tmp/yggit") else {
+            panic!("there is no editor to edit with")
+        };
+        assert_eq!(error.to_string(), "editor not found in configuration");
+    }
+
+    #[test]
+    fn test_only_stdin_reads_stdin() {
+        assert!(from_config(Ui::Stdin, None).reads_stdin());
+        assert!(!from_config(Ui::Terminal, Some("vim")).reads_stdin());
+        assert!(!from_config(Ui::None, None).reads_stdin());
+    }
+
+    #[test]
+    fn test_no_editor_refuses_to_edit() {
+        let editor = from_config(Ui::None, None);
+        let Err(error) = editor.edit("/tmp/yggit") else {
+            panic!("nothing should be edited")
+        };
+        assert!(error.to_string().contains("yggit.ui is none"));
+    }
+}
@@ -5,6 +5,8 @@ pub struct GitConfig {
     pub user: User,
     pub core: Core,
     pub yggit: Yggit,
+    /// How the notes commits are signed, None when commit.gpgsign is not set
+    pub signing: Option<Signing>,
 }
 
 #[derive(Debug)]
@@ -15,13 +17,93 @@ pub struct User {
 
 #[derive(Debug)]
 pub struct Core {
-    pub editor: String,
+    /// Required to edit the todo in the terminal
+    pub editor: Option<String>,
+    /// Pager used for long outputs
+    pub pager: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct Yggit {
-    // Default upstream of a branch
+    /// Default upstream of a branch
     pub default_upstream: String,
+    /// Reference where the notes are stored
+    pub notes_ref: String,
+    /// Maximum number of commits of a stack, 0 for no limit
+    pub max_stack_size: usize,
+    /// How the todo is edited
+    pub ui: Ui,
+    /// Namespace of the pushed references on the remote, refs/for/ for Gerrit
+    pub push_ref_prefix: String,
+    /// Main branch of the repository, main or master when not set
+    pub default_branch: Option<String>,
+}
+
+/// Format of the signatures, from gpg.format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+#[derive(Debug)]
+pub struct Signing {
+    pub format: SigningFormat,
+    /// gpg.<format>.program, or gpg.program for openpgp
+    pub program: String,
+    /// user.signingKey, the default key of the program when not set
+    pub key: Option<String>,
+}
+
+impl Signing {
+    fn parse(config: &git2::Config) -> Result<Signing> {
+        let format = match config.get_string("gpg.format").ok().as_deref() {
+            None | Some("openpgp") => SigningFormat::OpenPgp,
+            Some("x509") => SigningFormat::X509,
+            Some("ssh") => SigningFormat::Ssh,
+            Some(other) => {
+                return Err(anyhow::Error::msg(format!(
+                    "gpg.format should be openpgp, x509 or ssh, not \"{other}\""
+                )))
+            }
+        };
+        let program = match format {
+            SigningFormat::OpenPgp => config
+                .get_string("gpg.openpgp.program")
+                .or(config.get_string("gpg.program"))
+                .unwrap_or("gpg".to_string()),
+            SigningFormat::X509 => config
+                .get_string("gpg.x509.program")
+                .unwrap_or("gpgsm".to_string()),
+            SigningFormat::Ssh => config
+                .get_string("gpg.ssh.program")
+                .unwrap_or("ssh-keygen".to_string()),
+        };
+        let key = config.get_string("user.signingKey").ok();
+        if format == SigningFormat::Ssh && key.is_none() {
+            return Err(anyhow::Error::msg(
+                "user.signingKey is required to sign with ssh",
+            ));
+        }
+        Ok(Signing {
+            format,
+            program,
+            key,
+        })
+    }
+}
+
+/// How the todo is edited, see `crate::editor`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ui {
+    /// With the editor of the user
+    #[default]
+    Terminal,
+    /// The edited todo is read from stdin
+    Stdin,
+    /// Editing fails, for instance in the CI
+    None,
 }
 
 impl GitConfig {
@@ -30,8 +112,16 @@ impl GitConfig {
     /// It parses the following field:
     ///  - user.email : required
     ///  - user.name : required
-    ///  - notes.rewriteRef = "refs/notes/commits" : required
+    ///  - core.editor, VISUAL or EDITOR : required to edit when yggit.ui is terminal
+    ///  - core.pager : optional
+    ///  - notes.rewriteRef contains yggit.notesRef : required
     ///  - yggit.defaultUpstream : optional, default(origin)
+    ///  - yggit.notesRef : optional, default(refs/notes/commits)
+    ///  - yggit.maxStackSize : optional, default(100)
+    ///  - yggit.ui : optional, terminal|stdin|none, default(terminal)
+    ///  - yggit.pushRefPrefix : optional, default(refs/heads/)
+    ///  - yggit.defaultBranch : optional
+    ///  - commit.gpgSign, gpg.format, gpg.<format>.program and user.signingKey : optional
     pub fn parse(config: git2::Config) -> Result<GitConfig> {
         let email = config
             .get_string("user.email")
@@ -41,37 +131,144 @@ impl GitConfig {
             .get_string("user.name")
             .context("name not found in configuration")?;
 
-        let editor = (match config.get_string("core.editor") {
-            Ok(editor) => Ok(editor),
-            Err(_) => std::env::var("EDITOR").context("editor not found in configuration"),
-        })?;
-
-        // Force rewriteRef = "refs/notes/commits" to exist
-        let rewrite_ref = config
-            .get_string("notes.rewriteRef")
-            .context("notes.rewriteRef wasn't found")?;
-        if rewrite_ref != "refs/notes/commits" {
-            println!("rewriteRef should be set to \"refs/notes/commits\"");
-            return Err(anyhow::Error::msg(
-                "rewriteRef should be set to \"refs/notes/commits\"",
-            ));
+        let notes_ref = config
+            .get_string("yggit.notesRef")
+            .unwrap_or("refs/notes/commits".to_string());
+
+        let pager = config.get_string("core.pager").ok();
+
+        // Force rewriteRef to contain notes_ref, it can have several values
+        let mut rewrite_refs = Vec::default();
+        if let Ok(entries) = config.multivar("notes.rewriteRef", None) {
+            entries
+                .for_each(|entry| rewrite_refs.extend(entry.value().map(ToString::to_string)))
+                .context("cannot read notes.rewriteRef")?;
+        }
+        if rewrite_refs.is_empty() {
+            return Err(anyhow::Error::msg("notes.rewriteRef wasn't found"));
+        }
+        if !rewrite_refs.contains(&notes_ref) {
+            println!("rewriteRef should be set to \"{notes_ref}\"");
+            return Err(anyhow::Error::msg(format!(
+                "rewriteRef should be set to \"{notes_ref}\""
+            )));
         }
 
         let default_upstream = config
             .get_string("yggit.defaultUpstream")
             .unwrap_or("origin".to_string());
 
+        let max_stack_size = match config.get_i64("yggit.maxStackSize") {
+            Ok(size) => usize::try_from(size).context("yggit.maxStackSize should be positive")?,
+            Err(_) => 100,
+        };
+
+        let ui = match config.get_string("yggit.ui").ok().as_deref() {
+            None | Some("terminal") => Ui::Terminal,
+            Some("stdin") => Ui::Stdin,
+            Some("none") => Ui::None,
+            Some(other) => {
+                return Err(anyhow::Error::msg(format!(
+                    "yggit.ui should be terminal, stdin or none, not \"{other}\""
+                )))
+            }
+        };
+
+        // The editor is only needed to edit in the terminal, a missing one fails there
+        let editor = config
+            .get_string("core.editor")
+            .ok()
+            .or(std::env::var("VISUAL").ok())
+            .or(std::env::var("EDITOR").ok());
+
+        let push_ref_prefix = config
+            .get_string("yggit.pushRefPrefix")
+            .unwrap_or("refs/heads/".to_string());
+
+        let default_branch = config.get_string("yggit.defaultBranch").ok();
+
+        // The notes commits are signed like the other commits
+        let signing = if config.get_bool("commit.gpgSign").unwrap_or(false) {
+            Some(Signing::parse(&config)?)
+        } else {
+            None
+        };
+
         Ok(Self {
             user: User { email, name },
-            core: Core { editor },
-            yggit: Yggit { default_upstream },
+            core: Core { editor, pager },
+            yggit: Yggit {
+                default_upstream,
+                notes_ref,
+                max_stack_size,
+                ui,
+                push_ref_prefix,
+                default_branch,
+            },
+            signing,
         })
     }
 }
 
+/// Set a key of the config, returns a description of the change if the key has been modified
+///
+/// A key already set to another value is only overwritten with force
+fn config_set(
+    config: &mut git2::Config,
+    key: &str,
+    value: &str,
+    force: bool,
+) -> Result<Option<String>> {
+    match config.get_string(key) {
+        Ok(current) if current == value => return Ok(None),
+        Ok(current) if !force => {
+            return Err(anyhow::Error::msg(format!(
+                "{key} is already set to \"{current}\", use --force to set it to \"{value}\""
+            )))
+        }
+        _ => (),
+    }
+    config
+        .set_str(key, value)
+        .with_context(|| format!("cannot set {key}"))?;
+    Ok(Some(format!("{key} = {value}")))
+}
+
+impl GitConfig {
+    /// Write the configuration required by yggit
+    ///
+    /// It sets the following fields:
+    ///  - notes.rewriteRef = yggit.notesRef
+    ///  - yggit.defaultUpstream, if given
+    ///
+    /// Returns the list of the changes, empty if everything was already set
+    pub fn init(
+        config: &mut git2::Config,
+        default_upstream: Option<&str>,
+        force: bool,
+    ) -> Result<Vec<String>> {
+        let notes_ref = config
+            .get_string("yggit.notesRef")
+            .unwrap_or("refs/notes/commits".to_string());
+
+        let mut changes = Vec::default();
+        changes.extend(config_set(config, "notes.rewriteRef", &notes_ref, force)?);
+        if let Some(default_upstream) = default_upstream {
+            changes.extend(config_set(
+                config,
+                "yggit.defaultUpstream",
+                default_upstream,
+                force,
+            )?);
+        }
+        Ok(changes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::GitConfig;
+    use super::{GitConfig, SigningFormat, Ui};
+    use crate::editor;
     use anyhow::{Context, Result};
     use std::{fs::File, io::Write, path::Path};
     use tempfile::TempDir;
@@ -100,13 +297,50 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path).expect("should be open");
         assert_eq!(config.user.email, "kenobi@example.com");
         assert_eq!(config.user.name, "Obi-Wan");
-        assert_eq!(config.core.editor, "neovim");
+        assert_eq!(config.core.editor.as_deref(), Some("neovim"));
+        assert_eq!(config.core.pager, None);
         assert_eq!(config.yggit.default_upstream, "origin");
+        assert_eq!(config.yggit.notes_ref, "refs/notes/commits");
+        assert_eq!(config.yggit.max_stack_size, 100);
+        assert_eq!(config.yggit.ui, Ui::Terminal);
+        assert_eq!(config.yggit.push_ref_prefix, "refs/heads/");
+        assert!(config.signing.is_none());
+    }
+
+    #[test]
+    fn test_signing() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!(
+            "[user]\n",
+            "email = kenobi@example.com\n",
+            "name = Obi-Wan\n",
+            "signingKey = ~/.ssh/id_ed25519.pub\n",
+            "[core]\n",
+            "editor = neovim\n",
+            "[notes]\n",
+            "rewriteRef = refs/notes/commits\n",
+            "[commit]\n",
+            "gpgSign = true\n",
+            "[gpg]\n",
+            "format = ssh\n",
+        );
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+
+        let config = GitConfig::open(&path).expect("should be open");
+        let signing = config.signing.expect("the notes are signed");
+        assert_eq!(signing.format, SigningFormat::Ssh);
+        assert_eq!(signing.program, "ssh-keygen");
+        assert_eq!(signing.key.as_deref(), Some("~/.ssh/id_ed25519.pub"));
     }
 
     #[test]
@@ -125,7 +359,8 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path);
         assert!(config.is_err());
@@ -151,7 +386,8 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path);
         assert!(config.is_err());
@@ -164,6 +400,7 @@ mod tests {
     #[test]
     fn test_open_missing_editor() {
         let tmp_dir = TempDir::new().expect("should be created");
+        std::env::remove_var("VISUAL");
         std::env::remove_var("EDITOR");
 
         let config = concat!(
@@ -178,12 +415,15 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
-        let config = GitConfig::open(&path);
-        assert!(config.is_err());
+        // The editor is only looked for when something is edited
+        let config = GitConfig::open(&path).expect("should be ok");
+        assert_eq!(config.core.editor, None);
+        let editor = editor::from_config(config.yggit.ui, config.core.editor.as_deref());
         assert_eq!(
-            config.unwrap_err().to_string(),
+            editor.edit("todo").unwrap_err().to_string(),
             "editor not found in configuration"
         );
 
@@ -203,10 +443,11 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path).expect("should be ok");
-        assert_eq!(config.core.editor, "emacs");
+        assert_eq!(config.core.editor.as_deref(), Some("emacs"));
     }
 
     #[test]
@@ -225,7 +466,8 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path);
         assert!(config.is_err());
@@ -253,7 +495,8 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path);
         assert!(config.is_err());
@@ -263,6 +506,61 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_init() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!(
+            "[user]\n",
+            "email = kenobi@example.com\n",
+            "name = Obi-Wan\n",
+            "[core]\n",
+            "editor = neovim\n",
+        );
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+        assert!(GitConfig::open(&path).is_err());
+
+        let mut config = git2::Config::open(&path).expect("config should be open");
+        let changes = GitConfig::init(&mut config, Some("upstream"), false).expect("should be set");
+        assert_eq!(
+            changes,
+            vec![
+                "notes.rewriteRef = refs/notes/commits",
+                "yggit.defaultUpstream = upstream"
+            ]
+        );
+        let parsed = GitConfig::open(&path).expect("should be open after init");
+        assert_eq!(parsed.yggit.default_upstream, "upstream");
+
+        // Running it again changes nothing
+        let changes = GitConfig::init(&mut config, Some("upstream"), false).expect("should be set");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_init_does_not_clobber_rewrite_ref() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!("[notes]\n", "rewriteRef = wrong-value\n",);
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+
+        let mut config = git2::Config::open(&path).expect("config should be open");
+        let result = GitConfig::init(&mut config, None, false);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "notes.rewriteRef is already set to \"wrong-value\", use --force to set it to \"refs/notes/commits\""
+        );
+
+        let changes = GitConfig::init(&mut config, None, true).expect("should be forced");
+        assert_eq!(changes, vec!["notes.rewriteRef = refs/notes/commits"]);
+    }
+
     #[test]
     fn test_default_upstream() {
         let tmp_dir = TempDir::new().expect("should be created");
@@ -278,7 +576,8 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path).expect("should be open");
         assert_eq!(config.yggit.default_upstream, "origin");
@@ -301,9 +600,87 @@ mod tests {
 
         let path = tmp_dir.path().join(".gitconfig");
         let mut file = File::create(&path).expect("gitconfig should be created");
-        file.write(config.as_bytes()).expect("should be written");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
 
         let config = GitConfig::open(&path).expect("should be open");
         assert_eq!(config.yggit.default_upstream, "upstream");
     }
+
+    #[test]
+    fn test_notes_ref() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!(
+            "[user]\n",
+            "email = kenobi@example.com\n",
+            "name = Obi-Wan\n",
+            "[core]\n",
+            "editor = neovim\n",
+            "[notes]\n",
+            "rewriteRef = refs/notes/yggit\n",
+            "[yggit]\n",
+            "notesRef = refs/notes/yggit\n"
+        );
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+
+        let config = GitConfig::open(&path).expect("should be open");
+        assert_eq!(config.yggit.notes_ref, "refs/notes/yggit");
+    }
+
+    #[test]
+    fn test_notes_ref_not_rewritten() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!(
+            "[user]\n",
+            "email = kenobi@example.com\n",
+            "name = Obi-Wan\n",
+            "[core]\n",
+            "editor = neovim\n",
+            "[notes]\n",
+            "rewriteRef = refs/notes/commits\n",
+            "[yggit]\n",
+            "notesRef = refs/notes/yggit\n"
+        );
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+
+        let config = GitConfig::open(&path);
+        assert!(config.is_err());
+        assert_eq!(
+            config.unwrap_err().to_string(),
+            "rewriteRef should be set to \"refs/notes/yggit\""
+        )
+    }
+
+    #[test]
+    fn test_notes_ref_among_rewrite_refs() {
+        let tmp_dir = TempDir::new().expect("should be created");
+        let config = concat!(
+            "[user]\n",
+            "email = kenobi@example.com\n",
+            "name = Obi-Wan\n",
+            "[core]\n",
+            "editor = neovim\n",
+            "[notes]\n",
+            "rewriteRef = refs/notes/yggit\n",
+            "rewriteRef = refs/notes/commits\n",
+            "[yggit]\n",
+            "notesRef = refs/notes/yggit\n"
+        );
+
+        let path = tmp_dir.path().join(".gitconfig");
+        let mut file = File::create(&path).expect("gitconfig should be created");
+        file.write_all(config.as_bytes())
+            .expect("should be written");
+
+        let config = GitConfig::open(&path).expect("should be open");
+        assert_eq!(config.yggit.notes_ref, "refs/notes/yggit");
+    }
 }
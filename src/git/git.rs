@@ -1,11 +1,21 @@
-use super::config::GitConfig;
+use super::{config::GitConfig, BranchName, Origin};
+use crate::{
+    core::PushStrategy,
+    editor::{self, Editor},
+};
 use anyhow::{Context, Result};
 use auth_git2::GitAuthenticator;
-use git2::{Branch, BranchType, Error, ErrorCode, Oid, Repository, Signature};
+use git2::{
+    Branch, BranchType, ConfigLevel, Cred, CredentialType, DescribeFormatOptions, DescribeOptions,
+    Error, ErrorCode, Oid, Repository, RepositoryState, Signature, Sort,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    path::PathBuf,
-    process::Command,
+    cell::{OnceCell, RefCell},
+    collections::HashMap,
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::{Arc, Mutex},
 };
@@ -15,61 +25,796 @@ pub struct Git {
     signature: Signature<'static>,
     pub config: GitConfig,
     auth: GitAuthenticator,
+    /// The ssh methods tried by auth, reported when they all fail
+    auth_methods: Vec<String>,
+    /// Skip the check that HEAD descends from the base of the stack
+    assume_unchanged_base: bool,
+    /// Branch the stack is based on, the main branch by default
+    onto: Option<String>,
+    /// List the whole history instead of stopping at the base of the stack
+    root: bool,
+    /// Glob of the tags, the most recent matching tag is the base of the stack
+    since_tag: Option<String>,
+    /// Raw messages of the notes already read, invalidated on write
+    notes_cache: RefCell<HashMap<Oid, Option<String>>>,
+    /// Name of the main branch, detected once per instance
+    default_branch: OnceCell<String>,
+    /// Selected by yggit.ui
+    editor: Box<dyn Editor>,
 }
 
 pub struct EnhancedCommit<N> {
     pub id: Oid,
+    pub parents: Vec<Oid>,
     pub title: String,
     pub description: Option<String>,
     pub note: Option<N>,
 }
 
-#[allow(dead_code)]
+/// Commits of the stack after a rebase
+pub struct Rebased {
+    /// (old oid, new oid) of the replayed commits, from the base to the tip
+    pub rewritten: Vec<(Oid, Oid)>,
+    /// (old oid, oid of the commit already containing its change) of the commits that have not been replayed,
+    /// the commit is a replayed one or the new base
+    pub applied: Vec<(Oid, Oid)>,
+}
+
+/// Summary of the changes between two commits
+#[derive(Debug, PartialEq, Eq)]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl std::fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files changed, {} insertions(+), {} deletions(-)",
+            self.files_changed, self.insertions, self.deletions
+        )
+    }
+}
+
+/// Formatting of an existing note
+#[derive(Default)]
+struct NoteStyle {
+    pretty: bool,
+    trailing_newline: bool,
+}
+
+impl NoteStyle {
+    fn detect(message: &str) -> Self {
+        let trimmed = message.trim_end();
+        let pretty =
+            trimmed.contains('\n') && serde_json::from_str::<serde_json::Value>(trimmed).is_ok();
+        Self {
+            pretty,
+            trailing_newline: message.ends_with('\n'),
+        }
+    }
+
+    fn serialize<N>(&self, note: &N) -> serde_json::Result<String>
+    where
+        N: Serialize,
+    {
+        let mut note = if self.pretty {
+            serde_json::to_string_pretty(note)?
+        } else {
+            serde_json::to_string(note)?
+        };
+        if self.trailing_newline {
+            note.push('\n');
+        }
+        Ok(note)
+    }
+}
+
+/// The remote rejected the pushed branch, and has a tag with the same name
+///
+/// Git accepts a tag and a branch sharing a name, only a server refusing it is a conflict
+#[derive(Debug)]
+pub struct RefTypeConflict {
+    pub refname: String,
+    /// Message of the remote
+    pub reason: String,
+}
+
+impl std::fmt::Display for RefTypeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "remote rejected the branch: {}, {} already exists on the remote",
+            self.reason, self.refname
+        )
+    }
+}
+
+impl std::error::Error for RefTypeConflict {}
+
+/// The remote-tracking branches of the origin cannot be updated
+#[derive(Debug)]
+pub struct FetchFailed {
+    pub origin: Origin,
+    pub reason: String,
+}
+
+impl std::fmt::Display for FetchFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot fetch {}: {}", self.origin, self.reason)
+    }
+}
+
+impl std::error::Error for FetchFailed {}
+
+/// The https remote refused the credentials, or none could be found
+#[derive(Debug)]
+pub struct NoHttpsCredentials {
+    pub url: String,
+}
+
+impl std::fmt::Display for NoHttpsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no usable credentials for the https remote {}, set GIT_TOKEN or configure a credential helper",
+            self.url
+        )
+    }
+}
+
+impl std::error::Error for NoHttpsCredentials {}
+
+/// Every ssh authentication method has been refused by the remote
+#[derive(Debug)]
+pub struct SshAuthenticationFailed {
+    pub url: String,
+    /// The methods in the order they have been tried
+    pub tried: Vec<String>,
+}
+
+impl std::fmt::Display for SshAuthenticationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ssh authentication to {} failed, tried: {}, set YGGIT_SSH_KEY to use another key",
+            self.url,
+            self.tried.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for SshAuthenticationFailed {}
+
+/// Private keys of ~/.ssh tried first, in this order, like ssh does
+const DEFAULT_SSH_KEYS: [&str; 6] = [
+    "id_rsa",
+    "id_ecdsa",
+    "id_ecdsa_sk",
+    "id_ed25519",
+    "id_ed25519_sk",
+    "id_dsa",
+];
+
+/// Private keys of the directory: the default ones, then the others having a public key
+fn ssh_keys(ssh_dir: &Path) -> Vec<PathBuf> {
+    let mut keys: Vec<PathBuf> = DEFAULT_SSH_KEYS
+        .iter()
+        .map(|name| ssh_dir.join(name))
+        .filter(|key| key.is_file())
+        .collect();
+    let mut others: Vec<PathBuf> = std::fs::read_dir(ssh_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            let is_default = name.is_some_and(|name| DEFAULT_SSH_KEYS.contains(&name));
+            // id_work.pub is the public key of id_work
+            let has_public_key = path.extension().is_none() && path.with_extension("pub").is_file();
+            path.is_file() && !is_default && has_public_key
+        })
+        .collect();
+    others.sort();
+    keys.append(&mut others);
+    keys
+}
+
+/// Authenticator of the remotes, with the ssh methods it tries, in order
+///
+/// The ssh agent then every key of ~/.ssh are tried, unless a key is forced with YGGIT_SSH_KEY
+fn authenticator(
+    ssh_dir: Option<&Path>,
+    forced_key: Option<PathBuf>,
+) -> (GitAuthenticator, Vec<String>) {
+    let mut auth = GitAuthenticator::new_empty()
+        .try_cred_helper(true)
+        .try_password_prompt(3)
+        .add_default_username()
+        .prompt_ssh_key_password(true);
+    let mut tried = Vec::new();
+    let keys = match forced_key {
+        Some(key) => vec![key],
+        None => {
+            auth = auth.try_ssh_agent(true);
+            tried.push("ssh-agent".to_string());
+            ssh_dir.map(ssh_keys).unwrap_or_default()
+        }
+    };
+    for key in keys {
+        tried.push(key.display().to_string());
+        auth = auth.add_ssh_key_from_file(key, None);
+    }
+    (auth, tried)
+}
+
+/// Use the token for the first https authentication, then fall back to the other credentials
+///
+/// The token is only offered once, libgit2 asks again when it is refused
+fn with_token<'a, F>(
+    mut fallback: F,
+    token: Option<String>,
+) -> impl 'a + FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, Error>
+where
+    F: 'a + FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, Error>,
+{
+    let mut token = token;
+    move |url, username, allowed| {
+        if url.starts_with("https://") && allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some(token) = token.take() {
+                return Cred::userpass_plaintext(username.unwrap_or("x-access-token"), &token);
+            }
+        }
+        fallback(url, username, allowed)
+    }
+}
+
+/// Token of the https remotes, from GIT_TOKEN or GITHUB_TOKEN
+fn token() -> Option<String> {
+    std::env::var("GIT_TOKEN")
+        .or_else(|_| std::env::var("GITHUB_TOKEN"))
+        .ok()
+}
+
+/// Credential helper answering with the token of YGGIT_TOKEN, like `with_token`
+const TOKEN_CREDENTIAL_HELPER: &str =
+    "!f() { test \"$1\" = get && echo username=x-access-token && echo \"password=$YGGIT_TOKEN\"; }; f";
+
+/// Authenticate a git command like libgit2 is: with the token for https, and with the forced key for ssh
+///
+/// Both are given through the environment, they are never written in the command line.
+/// Without them, git uses its own credential helpers and ssh keys
+fn authenticate(command: &mut std::process::Command, token: Option<&str>, ssh_key: Option<&Path>) {
+    if let Some(token) = token {
+        // The empty helper clears the configured ones, the token is used instead of them
+        command
+            .args(["-c", "credential.helper="])
+            .arg("-c")
+            .arg(format!("credential.helper={TOKEN_CREDENTIAL_HELPER}"))
+            .env("YGGIT_TOKEN", token);
+    }
+    if let Some(ssh_key) = ssh_key {
+        command
+            .env(
+                "GIT_SSH_COMMAND",
+                "ssh -i \"$YGGIT_SSH_KEY\" -o IdentitiesOnly=yes",
+            )
+            .env("YGGIT_SSH_KEY", ssh_key);
+    }
+}
+
+/// Explain an authentication failure with the methods that have been tried, other errors are kept as is
+fn credentials_error(url: Option<&str>, err: Error, tried: &[String]) -> anyhow::Error {
+    match url {
+        Some(url) if err.code() == ErrorCode::Auth && url.starts_with("https://") => {
+            NoHttpsCredentials {
+                url: url.to_string(),
+            }
+            .into()
+        }
+        Some(url) if err.code() == ErrorCode::Auth => SshAuthenticationFailed {
+            url: url.to_string(),
+            tried: tried.to_vec(),
+        }
+        .into(),
+        _ => err.into(),
+    }
+}
+
+/// Returns true if libgit2 refused to update a reference without force
+fn is_not_fast_forward(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<Error>()
+            .is_some_and(|error| error.code() == ErrorCode::NotFastForward)
+    })
+}
+
+/// Status of a reference, as reported by the remote after a push
+#[derive(Debug, PartialEq)]
+struct RefStatus {
+    refname: String,
+    /// Message of the remote if the reference has been rejected
+    error: Option<String>,
+}
+
+/// Outcome of the push of a branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// The branch is created on the remote
+    New,
+    /// The branch of the remote is updated
+    Update,
+    /// The branch of the remote has diverged, force-with-lease refuses to update it
+    Diverged,
+    /// The branch of the remote is already at the same commit
+    UpToDate,
+}
+
+impl std::fmt::Display for PushOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let outcome = match self {
+            PushOutcome::New => "new branch",
+            PushOutcome::Update => "update",
+            PushOutcome::Diverged => "diverged",
+            PushOutcome::UpToDate => "up to date",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+/// Position of a branch compared to its remote-tracking branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingStatus {
+    /// There is no remote-tracking branch
+    NotPushed,
+    UpToDate,
+    /// The local branch has commits that the remote does not have
+    Ahead,
+    /// The remote has commits that the local branch does not have
+    Behind,
+    /// Both have commits that the other does not have
+    Diverged,
+}
+
+impl std::fmt::Display for TrackingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            TrackingStatus::NotPushed => "not pushed",
+            TrackingStatus::UpToDate => "up to date",
+            TrackingStatus::Ahead => "ahead",
+            TrackingStatus::Behind => "behind",
+            TrackingStatus::Diverged => "diverged",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+#[derive(Clone, Copy)]
 enum PushMode {
     Normal,
     Force,
-    ForceWithLease,
+    // The branch of the remote is expected to be on this commit
+    ForceWithLease(Oid),
 }
 
 impl Git {
     /// Open a repository at the given path
     /// Also load the signature from the .gitconfig
+    ///
+    /// GIT_DIR and GIT_WORK_TREE take precedence over the path, like in git hooks and aliases
     pub fn open(path: &str) -> Result<Self> {
+        Self::open_with_env(
+            path,
+            std::env::var_os("GIT_DIR"),
+            std::env::var_os("GIT_WORK_TREE"),
+        )
+    }
+
+    fn open_with_env(
+        path: &str,
+        git_dir: Option<OsString>,
+        work_tree: Option<OsString>,
+    ) -> Result<Self> {
         // The path can be absolute or not
-        let path = if path.starts_with('/') {
+        // C:\ is absolute too on Windows
+        let path = if Path::new(path).is_absolute() {
             PathBuf::from_str(path).context("invalid absolute path")?
         } else {
             let current_dir = std::env::current_dir().context("cannot open current directory")?;
             current_dir.join(path)
         };
-        let repository = Repository::discover(path).context("repository not found")?;
+        let repository = match git_dir {
+            Some(git_dir) => {
+                let repository =
+                    Repository::open(git_dir).context("GIT_DIR is not a repository")?;
+                if let Some(work_tree) = work_tree {
+                    repository
+                        .set_workdir(Path::new(&work_tree), false)
+                        .context("GIT_WORK_TREE cannot be used")?;
+                }
+                repository
+            }
+            None => Repository::discover(path).context("repository not found")?,
+        };
         let config = repository.config().context("config not found")?;
         let gitconfig = GitConfig::parse(config)?;
         let signature = Signature::now(&gitconfig.user.name, &gitconfig.user.email)
             .context("cannot compute signature")?;
+        let editor = editor::from_config(gitconfig.yggit.ui, gitconfig.core.editor.as_deref());
+        let ssh_dir = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh"));
+        let forced_key = std::env::var_os("YGGIT_SSH_KEY").map(PathBuf::from);
+        let (auth, auth_methods) = authenticator(ssh_dir.as_deref(), forced_key);
         Ok(Git {
             repository,
             signature,
             config: gitconfig,
-            auth: GitAuthenticator::new(),
+            auth,
+            auth_methods,
+            assume_unchanged_base: false,
+            onto: None,
+            root: false,
+            since_tag: None,
+            notes_cache: RefCell::new(HashMap::default()),
+            default_branch: OnceCell::new(),
+            editor,
         })
     }
 
+    /// Write the configuration required by yggit in the local config of the repository
+    ///
+    /// Returns the list of the changes
+    pub fn init(path: &str, default_upstream: Option<&str>, force: bool) -> Result<Vec<String>> {
+        let repository = Repository::discover(path).context("repository not found")?;
+        let mut config = repository
+            .config()
+            .and_then(|config| config.open_level(ConfigLevel::Local))
+            .context("local config not found")?;
+        GitConfig::init(&mut config, default_upstream, force)
+    }
+
+    /// Override the identity used to write the notes
+    ///
+    /// The name and the email fall back to the ones of the git configuration
+    pub fn override_identity(&mut self, name: Option<&str>, email: Option<&str>) -> Result<()> {
+        let name = name.unwrap_or(&self.config.user.name);
+        let email = email.unwrap_or(&self.config.user.email);
+        self.signature = Signature::now(name, email).context("cannot compute signature")?;
+        Ok(())
+    }
+
+    /// Set the branch the stack is based on
+    ///
+    /// When None, the stack is based on the main branch
+    pub fn set_onto(&mut self, onto: Option<String>) {
+        self.onto = onto;
+    }
+
+    /// Set the origin of the branches that do not have one, instead of yggit.defaultUpstream
+    ///
+    /// When None, yggit.defaultUpstream is kept
+    pub fn set_default_upstream(&mut self, origin: Option<&Origin>) {
+        if let Some(origin) = origin {
+            self.config.yggit.default_upstream = origin.to_string();
+        }
+    }
+
+    /// Replace the editor selected by yggit.ui
+    pub fn set_editor(&mut self, editor: Box<dyn Editor>) {
+        self.editor = editor;
+    }
+
+    /// Returns true if the edited todo is read from stdin, like with `--from-stdin`
+    pub fn editor_reads_stdin(&self) -> bool {
+        self.editor.reads_stdin()
+    }
+
+    /// Returns the name of the checked out branch, None when HEAD is detached
+    fn current_branch(&self) -> Option<String> {
+        let head = self.repository.head().ok()?;
+        if !head.is_branch() {
+            return None;
+        }
+        head.shorthand().map(str::to_string)
+    }
+
+    /// Remember the onto branch given to `set_onto` for the checked out branch, in `branch.<name>.yggitOnto`
+    ///
+    /// The base is resolved first, an onto that cannot be the base of the stack is not remembered
+    /// Nothing is done when HEAD is detached or there is no onto
+    pub fn remember_onto(&self) -> Result<()> {
+        let (Some(onto), Some(branch)) = (&self.onto, self.current_branch()) else {
+            return Ok(());
+        };
+        self.base_commit()?;
+        let mut config = self
+            .repository
+            .config()
+            .context("git config is not present")?;
+        config
+            .set_str(&format!("branch.{}.yggitOnto", branch), onto)
+            .with_context(|| format!("Cannot remember the onto branch of {branch}"))
+    }
+
+    /// Returns the onto branch remembered for the checked out branch, if any
+    pub fn remembered_onto(&self) -> Option<String> {
+        let branch = self.current_branch()?;
+        self.repository
+            .config()
+            .ok()?
+            .get_string(&format!("branch.{}.yggitOnto", branch))
+            .ok()
+    }
+
+    /// Base the stack on the most recent tag reachable from HEAD matching the glob
+    pub fn set_since_tag(&mut self, pattern: Option<String>) {
+        self.since_tag = pattern;
+    }
+
+    /// List the whole history, back to the root commit, instead of stopping at the base of the stack
+    pub fn include_root(&mut self, value: bool) {
+        self.root = value;
+    }
+
+    /// Trust the caller that HEAD still descends from the base of the stack
+    ///
+    /// It skips the ancestor check of `list_commits`, which is faster,
+    /// but if the base is not an ancestor of HEAD, the whole history will be listed
+    pub fn assume_unchanged_base(&mut self, value: bool) {
+        self.assume_unchanged_base = value;
+    }
+
     /// Returns the main branch of the repository
     ///
-    /// The branch can be either main or master
+    /// The branch is the one of yggit.defaultBranch, or else either main or master
     /// If main exists it will be returned as the main branch
     /// If main does not exist, master will be returned as the main branch
     pub fn main_branch(&self) -> Option<Branch> {
-        let branches = ["main", "master"];
+        let name = self.default_branch().ok()?;
+        self.repository.find_branch(name, BranchType::Local).ok()
+    }
+
+    /// Returns the name of the main branch, see `main_branch`
+    ///
+    /// The name is detected once per instance, a main branch created afterwards is not seen,
+    /// but a missing one is looked for again on the next call
+    pub fn default_branch(&self) -> Result<&str> {
+        if let Some(name) = self.default_branch.get() {
+            return Ok(name);
+        }
+        let exists = |branch: &str| {
+            self.repository
+                .find_branch(branch, BranchType::Local)
+                .is_ok()
+        };
+        // The configured branch is explicit, it takes precedence over main and master
+        if let Some(name) = &self.config.yggit.default_branch {
+            if exists(name) {
+                return Ok(self.default_branch.get_or_init(|| name.to_string()));
+            }
+        }
+        let name = ["main", "master"]
+            .into_iter()
+            .find(|branch| exists(branch))
+            .with_context(|| match &self.config.yggit.default_branch {
+                Some(name) => format!("{name} (yggit.defaultBranch), main or master to exist"),
+                None => "main/master to exist".to_string(),
+            })?;
+        Ok(self.default_branch.get_or_init(|| name.to_string()))
+    }
+
+    /// Returns the path of the .git directory
+    pub fn git_dir(&self) -> &Path {
+        self.repository.path()
+    }
+
+    /// Returns the path of the working tree, None for a bare repository
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.repository.workdir()
+    }
+
+    /// Open the repository of the git directory again, for instance from another thread
+    ///
+    /// The configuration is read again, the flags like onto are not kept
+    pub fn reopen(git_dir: &Path, work_tree: Option<&Path>) -> Result<Self> {
+        let path = git_dir.to_str().context("the git directory is not utf-8")?;
+        Self::open_with_env(
+            path,
+            Some(git_dir.as_os_str().to_owned()),
+            work_tree.map(|work_tree| work_tree.as_os_str().to_owned()),
+        )
+    }
+
+    /// Returns true if the repository is a shallow clone
+    ///
+    /// In a shallow clone the history is truncated,
+    /// so the force-with-lease and ancestor checks may be unreliable
+    pub fn is_shallow(&self) -> bool {
+        self.repository.is_shallow()
+    }
+
+    /// Returns the git operation in progress, like a rebase or a merge, if any
+    pub fn operation_in_progress(&self) -> Option<&'static str> {
+        match self.repository.state() {
+            RepositoryState::Clean => None,
+            RepositoryState::Merge => Some("merge"),
+            RepositoryState::Revert | RepositoryState::RevertSequence => Some("revert"),
+            RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+                Some("cherry-pick")
+            }
+            RepositoryState::Bisect => Some("bisect"),
+            RepositoryState::Rebase
+            | RepositoryState::RebaseInteractive
+            | RepositoryState::RebaseMerge => Some("rebase"),
+            RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => Some("am"),
+        }
+    }
+
+    /// Returns the commit the stack is based on
+    ///
+    /// It is the commit of onto if set, the head of the main branch otherwise
+    fn base_commit(&self) -> Result<git2::Commit> {
+        if let Some(pattern) = &self.since_tag {
+            return self.latest_tag_commit(pattern);
+        }
+
+        let Some(onto) = &self.onto else {
+            // Find the commit of the "main" branch
+            let main_branch = self.main_branch().context("main/master to exist")?;
+            let main = main_branch
+                .get()
+                .peel_to_commit()
+                .context("main branch is not found")?;
+            return self.fork_point(main);
+        };
+
+        let head = self.repository.head().context("There is no head")?;
+        if head.is_branch() && head.shorthand() == Some(onto.as_str()) {
+            return Err(anyhow::Error::msg(format!(
+                "cannot use the current branch {onto} as the base of the stack, \
+                 the base has to be one of its ancestors"
+            )));
+        }
+
+        let onto_commit = self.find_onto_commit(onto)?;
+
+        if head.peel_to_commit().map(|commit| commit.id()).ok() == Some(onto_commit.id()) {
+            return Err(anyhow::Error::msg(format!(
+                "{onto} points to HEAD, the base of the stack has to be one of its ancestors"
+            )));
+        }
+
+        Ok(onto_commit)
+    }
+
+    /// Returns the best common ancestor of the two commits, like `git merge-base`
+    pub fn merge_base(&self, a: Oid, b: Oid) -> Result<Oid> {
+        self.repository
+            .merge_base(a, b)
+            .with_context(|| format!("{a} and {b} have no common ancestor"))
+    }
+
+    /// Returns the commit where HEAD forked from the branch, so that the commits merged
+    /// in the branch after the fork are not part of the stack, like `git log main..HEAD`
+    ///
+    /// The branch commit is kept when HEAD is assumed to descend from it, or has no common ancestor with it
+    fn fork_point<'a>(&'a self, branch: git2::Commit<'a>) -> Result<git2::Commit<'a>> {
+        if self.assume_unchanged_base {
+            return Ok(branch);
+        }
+        let head = self
+            .repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .context("There is no head")?;
+        let Ok(base) = self.merge_base(head.id(), branch.id()) else {
+            return Ok(branch);
+        };
+        self.repository
+            .find_commit(base)
+            .context("Cannot find the fork point of the stack")
+    }
 
-        for branch in branches {
-            let branch = self.repository.find_branch(branch, BranchType::Local);
-            if let Ok(branch) = branch {
-                return Some(branch);
+    /// Returns the commit of onto, which is a hash, a local branch, a remote-tracking branch or a tag
+    ///
+    /// A branch gives the commit where HEAD forked from it
+    ///
+    /// An hexadecimal onto is first looked for as a full or abbreviated hash, then as a branch
+    fn find_onto_commit(&self, onto: &str) -> Result<git2::Commit> {
+        if onto.len() >= 4 && onto.chars().all(|c| c.is_ascii_hexdigit()) {
+            let commit = self
+                .repository
+                .revparse_single(onto)
+                .and_then(|object| object.peel_to_commit());
+            if let Ok(commit) = commit {
+                return Ok(commit);
             }
         }
-        None
+        if let Ok(branch) = self.repository.find_branch(onto, BranchType::Local) {
+            let commit = branch
+                .get()
+                .peel_to_commit()
+                .with_context(|| format!("branch {onto} does not point to a commit"))?;
+            return self.fork_point(commit);
+        }
+        // Like origin/main, when there is no local main
+        if let Ok(branch) = self.repository.find_branch(onto, BranchType::Remote) {
+            let commit = branch
+                .get()
+                .peel_to_commit()
+                .with_context(|| format!("branch {onto} does not point to a commit"))?;
+            return self.fork_point(commit);
+        }
+        self.repository
+            .revparse_single(&format!("refs/tags/{}", onto))
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("{onto} is neither a commit, a branch nor a tag"))
+    }
+
+    /// Returns the oids of HEAD, of the base of the stack and of the notes reference
+    ///
+    /// The listed stack stays the same as long as none of them moves
+    pub fn stack_state(&self) -> Result<(Oid, Oid, Option<Oid>)> {
+        let head = self
+            .repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .context("There is no head")?
+            .id();
+        // There is no base when the whole history is listed
+        let base = if self.root {
+            Oid::zero()
+        } else {
+            self.base_commit()?.id()
+        };
+        let notes = self
+            .repository
+            .find_reference(&self.config.yggit.notes_ref)
+            .ok()
+            .and_then(|reference| reference.target());
+        Ok((head, base, notes))
+    }
+
+    /// Returns the name of the base of the stack: the tag, the onto branch or the main branch
+    ///
+    /// There is no base when the whole history is listed
+    pub fn base_name(&self) -> Option<String> {
+        if self.root {
+            return None;
+        }
+        if let Some(pattern) = &self.since_tag {
+            return self.latest_tag(pattern).ok();
+        }
+        if let Some(onto) = &self.onto {
+            return Some(onto.clone());
+        }
+        self.default_branch().ok().map(ToString::to_string)
+    }
+
+    /// Returns the most recent tag reachable from HEAD matching the glob
+    fn latest_tag(&self, pattern: &str) -> Result<String> {
+        let head = self
+            .repository
+            .head()
+            .and_then(|head| head.peel(git2::ObjectType::Commit))
+            .context("There is no head")?;
+        head.describe(DescribeOptions::new().describe_tags().pattern(pattern))
+            .and_then(|describe| {
+                describe.format(Some(DescribeFormatOptions::new().abbreviated_size(0)))
+            })
+            .with_context(|| format!("no tag matching {pattern} is reachable from HEAD"))
+    }
+
+    /// Returns the commit of the most recent tag reachable from HEAD matching the glob
+    fn latest_tag_commit(&self, pattern: &str) -> Result<git2::Commit> {
+        let tag = self.latest_tag(pattern)?;
+        self.repository
+            .revparse_single(&format!("refs/tags/{}", tag))
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("tag {tag} does not point to a commit"))
     }
 
     /// List the commit in a repository with the attached note
@@ -77,32 +822,82 @@ impl Git {
     where
         N: DeserializeOwned,
     {
-        // Find the commit of the "main" branch
-        let main_branch = self.main_branch().context("main/master to exist")?;
+        // When the whole history is listed, the revwalk goes to the root commit
+        if self.root {
+            return self.list_commits_from(None);
+        }
+        self.list_commits_until_oid(self.base_commit()?.id())
+    }
+
+    /// List the commits from HEAD to the given base, excluded, with their notes
+    ///
+    /// The base has to be an ancestor of HEAD, whatever onto is set to
+    pub fn list_commits_until_oid<N>(&self, base: Oid) -> Result<Vec<EnhancedCommit<N>>>
+    where
+        N: DeserializeOwned,
+    {
+        self.list_commits_from(Some(base))
+    }
 
-        let main_commit = main_branch
-            .get()
-            .peel_to_commit()
-            .context("main branch is not found")?;
+    /// List the commits from HEAD to the base, to the root commit when there is none
+    fn list_commits_from<N>(&self, base: Option<Oid>) -> Result<Vec<EnhancedCommit<N>>>
+    where
+        N: DeserializeOwned,
+    {
+        let head = self
+            .repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .context("There is no head")?;
+
+        if let Some(base) = base {
+            if !self.assume_unchanged_base
+                && head.id() != base
+                && !self
+                    .repository
+                    .graph_descendant_of(head.id(), base)
+                    .context("Cannot compare HEAD with the base of the stack")?
+            {
+                return Err(anyhow::Error::msg(
+                    "HEAD is not a descendant of the base of the stack, rebase your branch first",
+                ));
+            }
+        }
 
         let mut revwalk = self
             .repository
             .revwalk()
             .context("Cannot rev walk the branch")?;
         revwalk.push_head().context("There is no head")?;
+        // Children are always listed before their parents
+        revwalk
+            .set_sorting(Sort::TOPOLOGICAL)
+            .context("Cannot sort the rev walk")?;
 
+        let notes = self.list_notes()?;
         let mut commits = Vec::default();
 
         for oid in revwalk {
             let oid = oid.context("not a valid oid")?;
 
-            if oid == main_commit.id() {
+            if Some(oid) == base {
                 break;
             }
 
+            // Stops before walking a whole history because of a wrong base
+            let max_stack_size = self.config.yggit.max_stack_size;
+            if max_stack_size != 0 && commits.len() == max_stack_size {
+                return Err(anyhow::Error::msg(format!(
+                    "the stack has more than {max_stack_size} commits, \
+                     use --onto to choose its base or raise yggit.maxStackSize"
+                )));
+            }
+
+            self.preload_note(oid, notes.get(&oid).copied())?;
+
             // The commit has to be found, because it's listed from the revwalk
             let commit = self
-                .find_commit(oid)
+                .find_commit(oid)?
                 .ok_or(anyhow::Error::msg("commit not found: not possible"))?;
 
             commits.push(commit);
@@ -111,34 +906,72 @@ impl Git {
         Ok(commits)
     }
 
-    fn push(&self, origin: &str, branch: &str, mode: PushMode) -> Result<()> {
-        println!("pushing {}:{}", origin, branch);
-        let fetch_refname = format!("refs/heads/{}", branch);
+    /// Push the branch to the origin
+    ///
+    /// With dry_run, the push of the given commit instead of the local branch is negotiated,
+    /// then it is aborted and only its outcome is returned
+    ///
+    /// The remote is not listed beforehand, the remote-tracking branch tells if the branch is created.
+    /// When it is missing while the branch exists on the remote, the push is forced again
+    fn push(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+        mode: PushMode,
+        dry_run: Option<Oid>,
+    ) -> Result<(PushOutcome, Vec<RefStatus>)> {
+        let exists = self.remote_branch_exists(origin, branch);
+        match self.push_once(origin, branch, mode, dry_run, exists) {
+            Err(error)
+                if !exists && !matches!(mode, PushMode::Normal) && is_not_fast_forward(&error) =>
+            {
+                self.push_once(origin, branch, mode, dry_run, true)
+            }
+            result => result,
+        }
+    }
+
+    /// Push the branch to the origin, once
+    ///
+    /// `exists` tells if the branch is on the remote, so that its update is forced
+    fn push_once(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+        mode: PushMode,
+        dry_run: Option<Oid>,
+        exists: bool,
+    ) -> Result<(PushOutcome, Vec<RefStatus>)> {
         let git_config = self
             .repository
             .config()
             .context("git config is not present")?;
+
         let mut push_options = git2::PushOptions::new();
 
         let mut remote_callbacks = git2::RemoteCallbacks::new();
-        remote_callbacks.credentials(self.auth.credentials(&git_config));
+        remote_callbacks.credentials(self.credentials(&git_config));
 
         enum PushError {
-            NotYetImplemented,
             NoUpdate,             // Should not happen
-            RemoteOriginDiverged, // Used when using force-with-lease
+            RemoteOriginDiverged, // Used when using force-with-lease or a normal push
         }
 
         enum PushStatus {
             Pushed,
             NewBranchPushed,
+            // The remote is already on the commit
+            UpToDate,
             Error(PushError),
         }
 
         let error: Arc<Mutex<Option<PushStatus>>> = Arc::new(Mutex::new(None));
         let cloned_external_variable = Arc::clone(&error);
 
-        remote_callbacks.push_negotiation(move |remote_updates| {
+        let refspec = self.refspec_for(branch, dry_run, &mode, exists);
+        let dry_run = dry_run.is_some();
+        let repository = &self.repository;
+        let negotiate = move |remote_updates: &[git2::PushUpdate]| {
             let mut status = cloned_external_variable.lock().unwrap();
             let null = git2::Oid::zero();
             let Some(remote_update) = remote_updates.iter().next() else {
@@ -151,35 +984,33 @@ impl Git {
                 return Ok(());
             }
 
+            if remote_update.src() == remote_update.dst() {
+                *status = Some(PushStatus::UpToDate);
+                return Ok(());
+            }
+
             match mode {
                 PushMode::Normal => {
                     // last commit of remote has to be known in current branch
-                    *status = Some(PushStatus::Error(PushError::NotYetImplemented));
-                    Err(Error::from_str("not yet implemented"))
+                    let fast_forward = repository
+                        .graph_descendant_of(remote_update.dst(), remote_update.src())
+                        .unwrap_or(false);
+                    if fast_forward {
+                        *status = Some(PushStatus::Pushed);
+                        Ok(())
+                    } else {
+                        *status = Some(PushStatus::Error(PushError::RemoteOriginDiverged));
+                        Err(Error::from_str("not a fast-forward"))
+                    }
                 }
                 PushMode::Force => {
                     *status = Some(PushStatus::Pushed);
                     Ok(())
                 }
-                PushMode::ForceWithLease => {
-                    // Comparing src with local origin
+                PushMode::ForceWithLease(expected) => {
+                    // Comparing src with the oid expected by the lease
                     let remote_origin_oid = remote_update.src();
-                    // Get the head of this branch
-                    let local_origin_oid = {
-                        let local_origin_name = remote_update
-                            .src_refname()
-                            .ok_or(Error::from_str("cannot parse source refname"))?;
-                        let upstream_name = local_origin_name
-                            .strip_prefix("refs/heads/")
-                            .ok_or(Error::from_str("cannot strip local origin name"))?;
-                        self.repository
-                            .find_reference(&format!("refs/remotes/{}/{}", origin, upstream_name))
-                            .ok()
-                            .and_then(|reference| reference.peel_to_commit().ok())
-                            .map(|commit| commit.id())
-                            .ok_or(Error::from_str("cannot find the commit reference hash"))?
-                    };
-                    if remote_origin_oid == local_origin_oid {
+                    if remote_origin_oid == expected {
                         *status = Some(PushStatus::Pushed);
                         Ok(())
                     } else {
@@ -188,368 +1019,1025 @@ impl Git {
                     }
                 }
             }
+        };
+        remote_callbacks.push_negotiation(move |remote_updates| {
+            let result = negotiate(remote_updates);
+            if dry_run {
+                // Nothing is sent to the remote
+                return Err(Error::from_str("dry run"));
+            }
+            result
+        });
+
+        // The server reports the status of each reference it received
+        let ref_statuses: Arc<Mutex<Vec<RefStatus>>> = Arc::new(Mutex::new(Vec::default()));
+        let cloned_ref_statuses = Arc::clone(&ref_statuses);
+
+        remote_callbacks.push_update_reference(move |refname, error| {
+            cloned_ref_statuses.lock().unwrap().push(RefStatus {
+                refname: refname.to_string(),
+                error: error.map(ToString::to_string),
+            });
+            Ok(())
         });
 
         push_options.remote_callbacks(remote_callbacks);
 
-        let mut remote = self
-            .repository
-            .find_remote(origin)
-            .context("Cannot find origin")?;
-        let _ = remote.push(
-            &[format!("+{}", fetch_refname).as_str()],
-            Some(&mut push_options),
-        );
+        let mut remote = self.find_remote(origin)?;
+        let push_result = remote.push(&[refspec], Some(&mut push_options));
 
-        let status = error.lock().unwrap();
+        let ref_statuses = std::mem::take(&mut *ref_statuses.lock().unwrap());
+        if let Some(RefStatus {
+            refname,
+            error: Some(error),
+        }) = ref_statuses.iter().find(|status| status.error.is_some())
+        {
+            // Some servers refuse a branch with the name of a tag,
+            // the remote is only listed to find out once the branch has been rejected
+            let conflicting_refname = format!("refs/tags/{}", branch);
+            if self.remote_refs(origin)?.contains(&conflicting_refname) {
+                return Err(RefTypeConflict {
+                    refname: conflicting_refname,
+                    reason: error.clone(),
+                }
+                .into());
+            }
+            return Err(anyhow::Error::msg(format!(
+                "remote rejected {refname}: {error}"
+            )));
+        }
+
+        let status = error.lock().unwrap();
         let status = status.as_ref();
-        match status {
-            Some(PushStatus::Error(PushError::NoUpdate)) => {
-                println!("no update to be done");
-                Err(anyhow::Error::msg("not pushed"))
+        // The push failed on its own, for instance because of the network, before or after the negotiation,
+        // only a push refused by the negotiation or aborted by the dry run is reported from its status
+        let negotiation_refused = matches!(status, Some(PushStatus::Error(_)));
+        let aborted = dry_run && status.is_some();
+        if let Err(err) = push_result {
+            if !negotiation_refused && !aborted {
+                return Err(credentials_error(remote.url(), err, &self.auth_methods))
+                    .with_context(|| format!("cannot push {origin}:{branch}"));
             }
-            Some(PushStatus::Error(PushError::NotYetImplemented)) => {
-                println!("not yet implemented");
-                Err(anyhow::Error::msg("not yet implemented"))
+        }
+        if dry_run {
+            return match status {
+                Some(PushStatus::Pushed) => Ok((PushOutcome::Update, ref_statuses)),
+                Some(PushStatus::NewBranchPushed) => Ok((PushOutcome::New, ref_statuses)),
+                Some(PushStatus::UpToDate) => Ok((PushOutcome::UpToDate, ref_statuses)),
+                Some(PushStatus::Error(PushError::RemoteOriginDiverged)) => {
+                    Ok((PushOutcome::Diverged, ref_statuses))
+                }
+                Some(PushStatus::Error(PushError::NoUpdate)) | None => {
+                    Ok((PushOutcome::UpToDate, ref_statuses))
+                }
+            };
+        }
+        match status {
+            Some(PushStatus::Error(PushError::NoUpdate)) | Some(PushStatus::UpToDate) => {
+                Ok((PushOutcome::UpToDate, ref_statuses))
             }
             Some(PushStatus::Error(PushError::RemoteOriginDiverged)) => {
-                println!("remote {origin}:{branch} has diverged");
                 Err(anyhow::Error::msg("remote has diverged"))
             }
-            Some(PushStatus::Pushed) => {
-                println!("{origin}:{branch} pushed");
-                Ok(())
-            }
-            Some(PushStatus::NewBranchPushed) => {
-                println!("{origin}:{branch} pushed, new branch created");
-                Ok(())
-            }
-            None => {
-                // TODO: this case should be removed
-                println!("this case should not happen");
-                Ok(())
-            }
+            Some(PushStatus::Pushed) => Ok((PushOutcome::Update, ref_statuses)),
+            Some(PushStatus::NewBranchPushed) => Ok((PushOutcome::New, ref_statuses)),
+            // The remote had nothing to negotiate
+            None => Ok((PushOutcome::UpToDate, ref_statuses)),
+        }
+    }
+
+    /// Credentials of the remotes: GIT_TOKEN or GITHUB_TOKEN for https, then ssh and the credential helper
+    fn credentials<'a>(
+        &'a self,
+        git_config: &'a git2::Config,
+    ) -> impl 'a + FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, Error> {
+        with_token(self.auth.credentials(git_config), token())
+    }
+
+    /// Returns the refspec pushing the branch with the mode
+    ///
+    /// A branch that does not exist on the remote yet is created without force,
+    /// some server hooks flag forced pushes even when they create branches
+    fn refspec_for(
+        &self,
+        branch: &BranchName,
+        commit: Option<Oid>,
+        mode: &PushMode,
+        exists: bool,
+    ) -> String {
+        let force = !matches!(mode, PushMode::Normal) && exists;
+        self.push_refspec(branch, commit, force)
+    }
+
+    /// Returns the refspec pushing the branch, in the namespace of yggit.pushRefPrefix on the remote
+    ///
+    /// The update is only forced in refs/heads/, unless it is a normal push,
+    /// the force and the lease are checked by yggit.
+    /// Other namespaces, like refs/for/ of Gerrit, expect plain pushes
+    /// The commit is pushed instead of the local branch when it is given
+    fn push_refspec(&self, branch: &BranchName, commit: Option<Oid>, force: bool) -> String {
+        let prefix = &self.config.yggit.push_ref_prefix;
+        let force = if force && prefix == "refs/heads/" {
+            "+"
+        } else {
+            ""
+        };
+        let source = match commit {
+            Some(commit) => commit.to_string(),
+            None => format!("refs/heads/{branch}"),
+        };
+        format!("{force}{source}:{prefix}{branch}")
+    }
+
+    /// Compare the commit of a branch with the remote-tracking branch of the origin
+    ///
+    /// Nothing is fetched, the remote-tracking branch is as recent as the last fetch
+    pub fn tracking_status(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+        local: Oid,
+    ) -> Result<TrackingStatus> {
+        let Some(remote) = self.remote_branch_oid(origin, branch) else {
+            return Ok(TrackingStatus::NotPushed);
+        };
+        let (ahead, behind) = self
+            .repository
+            .graph_ahead_behind(local, remote)
+            .with_context(|| format!("cannot compare {branch} with {origin}/{branch}"))?;
+        Ok(match (ahead, behind) {
+            (0, 0) => TrackingStatus::UpToDate,
+            (_, 0) => TrackingStatus::Ahead,
+            (0, _) => TrackingStatus::Behind,
+            _ => TrackingStatus::Diverged,
+        })
+    }
+
+    /// Find the remote of the given origin
+    ///
+    /// An origin that is not a named remote but looks like a url is used as an anonymous remote
+    fn find_remote(&self, origin: &Origin) -> Result<git2::Remote> {
+        match self.repository.find_remote(origin.as_str()) {
+            Ok(remote) => Ok(remote),
+            Err(_) if origin.is_url() => self
+                .repository
+                .remote_anonymous(origin.as_str())
+                .context("Cannot create anonymous remote"),
+            Err(err) => Err(err).context("Cannot find origin"),
+        }
+    }
+
+    /// List the names of the references of the remote
+    fn remote_refs(&self, origin: &Origin) -> Result<Vec<String>> {
+        let git_config = self
+            .repository
+            .config()
+            .context("git config is not present")?;
+        let mut remote_callbacks = git2::RemoteCallbacks::new();
+        remote_callbacks.credentials(self.credentials(&git_config));
+
+        let mut remote = self.find_remote(origin)?;
+        let url = remote.url().map(ToString::to_string);
+        let connection = remote
+            .connect_auth(git2::Direction::Fetch, Some(remote_callbacks), None)
+            .map_err(|err| credentials_error(url.as_deref(), err, &self.auth_methods))
+            .context("Cannot connect to the remote")?;
+        // git2 reads the empty list of an empty remote as a null slice, which is undefined behavior,
+        // an empty remote is detected by its missing HEAD instead
+        if connection.default_branch().is_err() {
+            return Ok(Vec::default());
+        }
+        let refs = connection
+            .list()
+            .context("Cannot list the references of the remote")?
+            .iter()
+            .map(|head| head.name().to_string())
+            .collect();
+        Ok(refs)
+    }
+
+    /// Equivalent of `git fetch <origin>`
+    ///
+    /// Only the remote-tracking branches are updated, the working tree and the local branches are left untouched
+    pub fn fetch(&self, origin: &Origin) -> Result<()> {
+        let failed = |reason: String| FetchFailed {
+            origin: origin.clone(),
+            reason,
+        };
+        let git_config = self
+            .repository
+            .config()
+            .map_err(|err| failed(err.to_string()))?;
+        let mut remote_callbacks = git2::RemoteCallbacks::new();
+        remote_callbacks.credentials(self.credentials(&git_config));
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks);
+
+        let mut remote = self
+            .find_remote(origin)
+            .map_err(|err| failed(format!("{err:#}")))?;
+        // The refspecs of the remote are used, an anonymous remote has none
+        remote
+            .fetch(&[] as &[&str], Some(&mut fetch_options), None)
+            .map_err(|err| {
+                failed(credentials_error(remote.url(), err, &self.auth_methods).to_string())
+            })?;
+        Ok(())
+    }
+
+    /// Delete the branch from the remote
+    ///
+    /// Nothing is done when the remote does not have the branch, returns false then
+    pub fn delete_remote_branch(&self, origin: &Origin, branch: &BranchName) -> Result<bool> {
+        let refname = format!("refs/heads/{}", branch);
+        if !self.remote_refs(origin)?.contains(&refname) {
+            return Ok(false);
+        }
+        self.delete_remote_ref(origin, &refname)?;
+        Ok(true)
+    }
+
+    /// Equivalent of `git push <origin> :<refname>`
+    pub fn delete_remote_ref(&self, origin: &Origin, refname: &str) -> Result<()> {
+        let git_config = self
+            .repository
+            .config()
+            .context("git config is not present")?;
+        let mut push_options = git2::PushOptions::new();
+        let mut remote_callbacks = git2::RemoteCallbacks::new();
+        remote_callbacks.credentials(self.credentials(&git_config));
+
+        let rejected: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let cloned_rejected = Arc::clone(&rejected);
+        remote_callbacks.push_update_reference(move |_, error| {
+            *cloned_rejected.lock().unwrap() = error.map(ToString::to_string);
+            Ok(())
+        });
+        push_options.remote_callbacks(remote_callbacks);
+
+        let mut remote = self.find_remote(origin)?;
+        remote
+            .push(&[format!(":{}", refname).as_str()], Some(&mut push_options))
+            .map_err(|err| credentials_error(remote.url(), err, &self.auth_methods))
+            .context("Cannot delete the remote reference")?;
+
+        if let Some(error) = rejected.lock().unwrap().take() {
+            return Err(anyhow::Error::msg(format!(
+                "remote rejected the deletion of {refname}: {error}"
+            )));
         }
+        Ok(())
     }
 
     /// Equivalent of `git push --force-with-lease`
-    pub fn push_force_with_lease(&self, origin: &str, branch: &str) -> Result<()> {
-        self.push(origin, branch, PushMode::ForceWithLease)
+    ///
+    /// The branch of the remote is expected to be on its remote-tracking branch
+    pub fn push_force_with_lease(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+    ) -> Result<PushOutcome> {
+        // A branch that has never been fetched is expected to be missing on the remote
+        let expected = self
+            .remote_branch_oid(origin, branch)
+            .unwrap_or(Oid::zero());
+        self.push_force_with_lease_expecting(origin, branch, expected)
+    }
+
+    /// Equivalent of `git push --force-with-lease=<branch>:<expected>`
+    ///
+    /// The push is refused if the branch of the remote is not on the expected commit,
+    /// whatever the last fetch said
+    pub fn push_force_with_lease_expecting(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+        expected: Oid,
+    ) -> Result<PushOutcome> {
+        self.push(origin, branch, PushMode::ForceWithLease(expected), None)
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Returns true if the remote-tracking branch `refs/remotes/<origin>/<branch>` exists
+    pub fn remote_branch_exists(&self, origin: &Origin, branch: &BranchName) -> bool {
+        self.remote_branch_oid(origin, branch).is_some()
+    }
+
+    /// Returns the commit of the remote-tracking branch, None when the branch has never been fetched
+    pub fn remote_branch_oid(&self, origin: &Origin, branch: &BranchName) -> Option<Oid> {
+        self.repository
+            .find_reference(&format!("refs/remotes/{}/{}", origin, branch))
+            .and_then(|reference| reference.peel_to_commit())
+            .map(|commit| commit.id())
+            .ok()
+    }
+
+    /// Equivalent of `git push`, the branch of the remote is only fast-forwarded
+    pub fn push_normal(&self, origin: &Origin, branch: &BranchName) -> Result<PushOutcome> {
+        self.push(origin, branch, PushMode::Normal, None)
+            .map(|(outcome, _)| outcome)
     }
 
     /// Equivalent of `git push --force`
-    pub fn push_force(&self, origin: &str, branch: &str) -> Result<()> {
-        self.push(origin, branch, PushMode::Force)
+    pub fn push_force(&self, origin: &Origin, branch: &BranchName) -> Result<PushOutcome> {
+        self.push(origin, branch, PushMode::Force, None)
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Push the branches to the origin in a single atomic push, either all of them are updated or none is
+    ///
+    /// libgit2 cannot ask the remote for an atomic push, so `git push --atomic` is run,
+    /// it is given GIT_TOKEN and YGGIT_SSH_KEY, see `authenticate`.
+    /// Returns the outcome of each branch, in the given order,
+    /// or None without pushing anything when the remote does not support atomic pushes
+    pub fn push_atomic(
+        &self,
+        origin: &Origin,
+        branches: &[(BranchName, PushStrategy)],
+    ) -> Result<Option<Vec<Result<PushOutcome>>>> {
+        let prefix = &self.config.yggit.push_ref_prefix;
+        let mut push = std::process::Command::new("git");
+        push.arg("--git-dir").arg(self.git_dir());
+        authenticate(
+            &mut push,
+            token().as_deref(),
+            std::env::var_os("YGGIT_SSH_KEY").as_deref().map(Path::new),
+        );
+        push.args(["push", "--atomic", "--porcelain"]);
+        for (branch, strategy) in branches {
+            if *strategy == PushStrategy::Lease {
+                // An empty lease expects the branch to be missing on the remote
+                let expected = self
+                    .remote_branch_oid(origin, branch)
+                    .map(|oid| oid.to_string())
+                    .unwrap_or_default();
+                push.arg(format!("--force-with-lease={prefix}{branch}:{expected}"));
+            }
+        }
+        push.arg(origin.as_str());
+        for (branch, strategy) in branches {
+            // A new branch is created without force, like `push`
+            let force =
+                *strategy == PushStrategy::Force && self.remote_branch_exists(origin, branch);
+            push.arg(self.push_refspec(branch, None, force));
+        }
+        let output = push.output().context("cannot run git push")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("does not support --atomic push") {
+            return Ok(None);
+        }
+
+        // Each reference is reported as `<flag>\t<src>:<dst>\t<summary>`
+        let statuses: HashMap<&str, (char, &str)> = stdout
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let flag = fields.next()?.chars().next()?;
+                let (_, refname) = fields.next()?.split_once(':')?;
+                Some((refname, (flag, fields.next().unwrap_or_default())))
+            })
+            .collect();
+        let outcomes = branches
+            .iter()
+            .map(
+                |(branch, _)| match statuses.get(format!("{prefix}{branch}").as_str()) {
+                    Some(('*', _)) => Ok(PushOutcome::New),
+                    Some((' ' | '+', _)) => Ok(PushOutcome::Update),
+                    Some(('=', _)) => Ok(PushOutcome::UpToDate),
+                    Some((_, summary)) => Err(anyhow::Error::msg(format!(
+                        "remote rejected {prefix}{branch}: {summary}"
+                    ))),
+                    None => Err(anyhow::Error::msg(format!(
+                        "cannot push {origin}:{branch}: {}",
+                        stderr.trim()
+                    ))),
+                },
+            )
+            .collect();
+        Ok(Some(outcomes))
+    }
+
+    /// Returns what pushing the commit to the branch with the strategy would do, without changing the remote
+    ///
+    /// The lease and the fast-forward are checked like `push_force_with_lease` and `push_normal`,
+    /// neither the local branch nor the commit need to be on each other
+    pub fn dry_push(
+        &self,
+        origin: &Origin,
+        branch: &BranchName,
+        commit: Oid,
+        strategy: PushStrategy,
+    ) -> Result<PushOutcome> {
+        let mode = match strategy {
+            PushStrategy::Force => PushMode::Force,
+            // A branch that has never been fetched is expected to be missing on the remote
+            PushStrategy::Lease => PushMode::ForceWithLease(
+                self.remote_branch_oid(origin, branch)
+                    .unwrap_or(Oid::zero()),
+            ),
+            PushStrategy::Normal => PushMode::Normal,
+        };
+        self.push(origin, branch, mode, Some(commit))
+            .map(|(outcome, _)| outcome)
     }
 
     /// Delete a note
     ///
     /// Does not return any error when you delete nothing
     pub fn delete_note(&self, oid: &Oid) -> Result<()> {
-        let result = self
-            .repository
-            .note_delete(*oid, None, &self.signature, &self.signature);
+        self.invalidate_note(*oid);
+        let result = self.repository.note_delete(
+            *oid,
+            Some(&self.config.yggit.notes_ref),
+            &self.signature,
+            &self.signature,
+        );
         if let Err(ref err) = result {
             if err.code() == ErrorCode::NotFound {
                 return Ok(());
             }
         }
-        result.context("cannot delete note")
+        result.context("cannot delete note")?;
+        self.sign_notes();
+        Ok(())
+    }
+
+    /// Sign the commit at the tip of the notes reference, when commit.gpgSign is set
+    ///
+    /// The notes are left unsigned with a warning when they cannot be signed
+    fn sign_notes(&self) {
+        if let Err(error) = self.try_sign_notes() {
+            eprintln!("warning: {error:#}, the notes are not signed");
+        }
+    }
+
+    fn try_sign_notes(&self) -> Result<()> {
+        let Some(signing) = &self.config.signing else {
+            return Ok(());
+        };
+        let mut reference = self
+            .repository
+            .find_reference(&self.config.yggit.notes_ref)
+            .context("cannot find the notes reference")?;
+        let commit = reference
+            .peel_to_commit()
+            .context("the notes reference does not point to a commit")?;
+        let parents = commit.parents().collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        let buffer = self
+            .repository
+            .commit_create_buffer(
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
+                &commit.tree().context("cannot read the notes tree")?,
+                &parents,
+            )
+            .context("cannot create the notes commit")?;
+        let content = buffer
+            .as_str()
+            .context("the notes commit is not utf-8")?
+            .to_string();
+        let signature = super::signing::sign(signing, &content)?;
+        let signed = self
+            .repository
+            .commit_signed(&content, &signature, None)
+            .context("cannot write the signed notes commit")?;
+        reference
+            .set_target(signed, "yggit: sign the notes")
+            .context("cannot update the notes reference")?;
+        Ok(())
+    }
+
+    /// Returns the raw message of the note of a given oid
+    ///
+    /// The messages are cached for the lifetime of this instance
+    /// A missing note is None, a broken notes reference is an error
+    fn find_note_message(&self, oid: Oid) -> Result<Option<String>> {
+        if let Some(message) = self.notes_cache.borrow().get(&oid) {
+            return Ok(message.clone());
+        }
+        let message = match self
+            .repository
+            .find_note(Some(&self.config.yggit.notes_ref), oid)
+        {
+            Ok(note) => note.message().map(|str| str.to_string()),
+            Err(err) if err.code() == ErrorCode::NotFound => None,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("cannot read the notes of {}", self.config.yggit.notes_ref)
+                })
+            }
+        };
+        self.notes_cache.borrow_mut().insert(oid, message.clone());
+        Ok(message)
+    }
+
+    /// Returns the oids of the notes, by annotated commit
+    ///
+    /// The notes reference is walked once, instead of looking up the note of each commit
+    fn list_notes(&self) -> Result<HashMap<Oid, Oid>> {
+        let notes = match self.repository.notes(Some(&self.config.yggit.notes_ref)) {
+            Ok(notes) => notes,
+            Err(err) if err.code() == ErrorCode::NotFound => return Ok(HashMap::default()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("cannot read the notes of {}", self.config.yggit.notes_ref)
+                })
+            }
+        };
+        notes
+            .map(|note| note.map(|(note, annotated)| (annotated, note)))
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("cannot read the notes of {}", self.config.yggit.notes_ref))
+    }
+
+    /// Cache the message of the note of a given oid, from the oid of the note found by `list_notes`
+    fn preload_note(&self, oid: Oid, note: Option<Oid>) -> Result<()> {
+        if self.notes_cache.borrow().contains_key(&oid) {
+            return Ok(());
+        }
+        let message = match note {
+            Some(note) => {
+                let blob = self
+                    .repository
+                    .find_blob(note)
+                    .with_context(|| format!("cannot read the note of {oid}"))?;
+                std::str::from_utf8(blob.content()).ok().map(str::to_string)
+            }
+            None => None,
+        };
+        self.notes_cache.borrow_mut().insert(oid, message);
+        Ok(())
+    }
+
+    /// Forget the cached note of a given oid
+    fn invalidate_note(&self, oid: Oid) {
+        self.notes_cache.borrow_mut().remove(&oid);
+    }
+
+    /// Returns true if the commit has a note, whatever its content
+    pub fn has_note(&self, oid: Oid) -> Result<bool> {
+        Ok(self.find_note_message(oid)?.is_some())
+    }
+
+    /// Returns the commit of a revision, like `git rev-parse <revision>^{commit}`
+    pub fn resolve_commit(&self, revision: &str) -> Result<Oid> {
+        self.repository
+            .revparse_single(revision)
+            .and_then(|object| object.peel_to_commit())
+            .map(|commit| commit.id())
+            .with_context(|| format!("{revision} is not a commit"))
+    }
+
+    /// Copy the note of a commit to another one
+    ///
+    /// The note is copied as is, nothing is done if there is no note
+    pub fn copy_note(&self, from: Oid, to: Oid) -> Result<()> {
+        let Some(message) = self.find_note_message(from)? else {
+            return Ok(());
+        };
+        self.invalidate_note(to);
+        self.repository
+            .note(
+                &self.signature,
+                &self.signature,
+                Some(&self.config.yggit.notes_ref),
+                to,
+                &message,
+                true,
+            )
+            .context("cannot copy note")?;
+        self.sign_notes();
+        Ok(())
     }
 
     /// Set the note of a given oid
     ///
     /// The note will be serialize to json format
-    pub fn set_note<N>(&self, oid: Oid, note: N) -> Result<()>
+    /// If the commit already has a note, its formatting (pretty or compact json,
+    /// trailing newline) is kept so that other tools sharing the note are not disturbed
+    /// Returns the oid of the written note
+    pub fn set_note<N>(&self, oid: Oid, note: N) -> Result<Oid>
     where
         N: Serialize,
     {
-        let note = serde_json::to_string(&note).context("Cannot convert note to json string")?;
-
-        self.repository
-            .note(&self.signature, &self.signature, None, oid, &note, true)
-            .map(|_| ())
-            .context("cannot write note")
+        let style = self
+            .find_note_message(oid)?
+            .map(|message| NoteStyle::detect(&message))
+            .unwrap_or_default();
+        let note = style
+            .serialize(&note)
+            .context("Cannot convert note to json string")?;
+        self.invalidate_note(oid);
+
+        let note = self
+            .repository
+            .note(
+                &self.signature,
+                &self.signature,
+                Some(&self.config.yggit.notes_ref),
+                oid,
+                &note,
+                true,
+            )
+            .context("cannot write note")?;
+        // The signature is on the notes commit, the note itself is unchanged
+        self.sign_notes();
+        Ok(note)
     }
 
     /// Returns the note of a given oid
-    fn find_note<N>(&self, oid: Oid) -> Option<N>
+    ///
+    /// Fails when the notes cannot be read, which is different from a missing note
+    pub fn find_note<N>(&self, oid: Oid) -> Result<Option<N>>
     where
         N: DeserializeOwned,
     {
-        self.repository
-            .find_note(None, oid)
-            .map(|note| note.message().map(|str| str.to_string()))
-            .ok()
-            .flatten()
-            .and_then(|string| {
-                // Removes empty lines
-                // Takes the last line
-                // So that it's compatible with merging fixup commits
-                // When two commits are merged, the note are also merged
-                // The note of the most recent commit is taking into account then
-                string
-                    .split('\n')
-                    .filter(|str| !str.trim().is_empty())
-                    .last()
-                    .map(ToString::to_string)
-            })
-            .and_then(|str| serde_json::from_str(&str).ok())
+        let Some(string) = self.find_note_message(oid)? else {
+            return Ok(None);
+        };
+        // A note written on several lines (pretty json) is parsed as a whole
+        if let Ok(note) = serde_json::from_str(&string) {
+            return Ok(Some(note));
+        }
+        // Removes empty lines
+        // Takes the last line
+        // So that it's compatible with merging fixup commits
+        // When two commits are merged, the note are also merged
+        // The note of the most recent commit is taking into account then
+        Ok(string
+            .split('\n')
+            .filter(|str| !str.trim().is_empty())
+            .last()
+            .and_then(|str| serde_json::from_str(str).ok()))
     }
 
     /// Retrieve a commit with its node
-    pub fn find_commit<N>(&self, oid: Oid) -> Option<EnhancedCommit<N>>
+    ///
+    /// Returns None if the commit does not exist
+    pub fn find_commit<N>(&self, oid: Oid) -> Result<Option<EnhancedCommit<N>>>
     where
         N: DeserializeOwned,
     {
         // Get the commit
-        let commit = self.repository.find_commit(oid).ok()?;
+        let Ok(commit) = self.repository.find_commit(oid) else {
+            return Ok(None);
+        };
         // Get the associated note
-        let note: Option<N> = self.find_note(oid);
+        let note: Option<N> = self.find_note(oid)?;
         // Get the title and the description
         let mut message = commit.message().unwrap_or_default().splitn(2, '\n');
         // Title is on the first line of the message
         let title = message.next().unwrap_or_default().to_string();
-        // Remaining lines are for the description
-        let description = message.next().map(str::to_string);
-
-        Some(EnhancedCommit {
+        // Remaining lines are for the description, after the blank line
+        let description = message
+            .next()
+            .map(|description| description.trim_start_matches('\n').trim_end())
+            .filter(|description| !description.is_empty())
+            .map(str::to_string);
+
+        Ok(Some(EnhancedCommit {
             id: oid,
+            parents: commit.parent_ids().collect(),
             title,
             description,
             note,
-        })
+        }))
+    }
+
+    /// Returns the path of the linked worktree where the branch is checked out, if any
+    pub fn branch_checked_out_elsewhere(&self, branch: &BranchName) -> Result<Option<PathBuf>> {
+        let worktrees = self
+            .repository
+            .worktrees()
+            .context("Cannot list worktrees")?;
+        for name in worktrees.iter().flatten() {
+            let worktree = self
+                .repository
+                .find_worktree(name)
+                .with_context(|| format!("Cannot open worktree {name}"))?;
+            // A pruned worktree cannot be opened
+            let Ok(repository) = Repository::open_from_worktree(&worktree) else {
+                continue;
+            };
+            let head = repository.head();
+            if let Ok(head) = head {
+                if head.is_branch() && head.shorthand() == Some(branch.as_str()) {
+                    return Ok(Some(worktree.path().to_path_buf()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the commit of the local branch, None if it does not exist
+    pub fn branch_commit(&self, branch: &BranchName) -> Option<Oid> {
+        self.repository
+            .find_branch(branch.as_str(), BranchType::Local)
+            .ok()
+            .and_then(|branch| branch.get().target())
     }
 
     /// Set the head of the given branch to the given commit
-    pub fn set_branch_to_commit(&self, branch: &str, oid: Oid) -> Result<()> {
+    ///
+    /// A branch checked out in another worktree is not moved
+    ///
+    /// Returns the previous tip of the branch, None if the branch has been created
+    /// A created branch tracks the branch of the same name on the given upstream
+    pub fn set_branch_to_commit(
+        &self,
+        branch: &BranchName,
+        oid: Oid,
+        upstream: Option<&Origin>,
+    ) -> Result<Option<Oid>> {
+        if let Some(path) = self.branch_checked_out_elsewhere(branch)? {
+            return Err(anyhow::Error::msg(format!(
+                "cannot move {branch}, it is checked out in the worktree {}",
+                path.display()
+            )));
+        }
+
         let commit = self
             .repository
             .find_commit(oid)
             .context("Cannot find commit")?;
 
+        let previous = self.branch_commit(branch);
+
         self.repository
-            .branch(branch, &commit, true)
+            .branch(branch.as_str(), &commit, true)
             .context("Cannot find branch")?;
 
-        Ok(())
+        if let (None, Some(upstream)) = (previous, upstream) {
+            // Written in the config, the remote branch does not have to exist yet
+            let mut config = self
+                .repository
+                .config()
+                .context("git config is not present")?;
+            config
+                .set_str(&format!("branch.{}.remote", branch), upstream.as_str())
+                .and_then(|_| {
+                    config.set_str(
+                        &format!("branch.{}.merge", branch),
+                        &format!("refs/heads/{}", branch),
+                    )
+                })
+                .with_context(|| format!("Cannot set the upstream of {branch}"))?;
+        }
+
+        Ok(previous)
     }
 
-    /// Open the given file with the user's editor and returns the content of this file
-    pub fn edit_file(&self, file_path: &str) -> Result<String> {
-        let output = Command::new(&self.config.core.editor)
-            .arg(file_path)
-            .status()
-            .context("Failed to open editor")?;
-        let true = output.success() else {
-            return Err(anyhow::Error::msg("Editor did not end successfully"));
+    /// Compute the number of changed files, insertions and deletions between two commits
+    pub fn diff_stat(&self, from: Oid, to: Oid) -> Result<DiffStat> {
+        let tree = |oid: Oid| {
+            self.repository
+                .find_commit(oid)
+                .and_then(|commit| commit.tree())
+                .context("Cannot find tree of commit")
         };
-        let content =
-            std::fs::read_to_string(file_path).context("Cannot read string from editor")?;
-        Ok(content)
+        let diff = self
+            .repository
+            .diff_tree_to_tree(Some(&tree(from)?), Some(&tree(to)?), None)
+            .context("Cannot compute diff")?;
+        let stats = diff.stats().context("Cannot compute diff stats")?;
+        Ok(DiffStat {
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use git2::Oid;
-    use serde::Serialize;
-    use std::{
-        io::Write,
-        process::{Command, Stdio},
-    };
-    use tempfile::TempDir;
+    /// Replay the commits of the stack on top of the given revision
+    ///
+    /// The notes are copied to the rewritten commits, not to the commits containing the applied ones.
+    /// If a conflict happens the rebase is aborted and HEAD is restored
+    pub fn rebase_onto(&self, new_base: &str) -> Result<Rebased> {
+        if self.root {
+            return Err(anyhow::Error::msg(
+                "cannot rebase the whole history, the stack needs a base",
+            ));
+        }
+        let base = self.base_commit()?;
+        let new_base = self
+            .repository
+            .revparse_single(new_base)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("{new_base} not found"))?;
+        let head = self.repository.head().context("There is no head")?;
 
-    use crate::git::config::{Core, GitConfig, User, Yggit};
+        let branch = self
+            .repository
+            .reference_to_annotated_commit(&head)
+            .context("Cannot read HEAD")?;
+        let upstream = self
+            .repository
+            .find_annotated_commit(base.id())
+            .context("Cannot read the base of the stack")?;
+        let onto = self
+            .repository
+            .find_annotated_commit(new_base.id())
+            .context("Cannot read the new base")?;
 
-    use super::Git;
+        let mut rebase = self
+            .repository
+            .rebase(Some(&branch), Some(&upstream), Some(&onto), None)
+            .context("Cannot start the rebase")?;
+
+        let mut rewritten = Vec::default();
+        let mut applied = Vec::default();
+        // The last replayed commit, it contains the change of a commit that is already applied
+        let mut tip = new_base.id();
+        while let Some(operation) = rebase.next() {
+            let operation = match operation {
+                Ok(operation) => operation,
+                Err(err) => {
+                    rebase.abort().context("Cannot abort the rebase")?;
+                    return Err(anyhow::Error::new(err).context("Cannot apply commit"));
+                }
+            };
+            let old = operation.id();
+
+            let has_conflicts = self
+                .repository
+                .index()
+                .map(|index| index.has_conflicts())
+                .context("Cannot read the index")?;
+            if has_conflicts {
+                rebase.abort().context("Cannot abort the rebase")?;
+                return Err(anyhow::Error::msg(format!(
+                    "conflict while applying {old}, the rebase has been aborted"
+                )));
+            }
 
-    macro_rules! execute_commands {
-        ($($cmd:expr $(, $arg:expr)*)* ) => {
-            {
-                $(
-                    let cmd_string = format!("{} {}", $cmd, vec![$($arg),*].join(" "));
-                    println!("{}", cmd_string);
-                    let child = Command::new($cmd)
-                        $(.arg($arg))*
-                        .stdout(Stdio::piped())
-                        .spawn()
-                        .expect("Failed to spawn child process");
-
-                    let output = child.wait_with_output().expect("Failed to read stdout");
-                    if !output.status.success() {
-                        panic!("the command did not succeed");
-                    }
-                    String::from_utf8(output.stdout).expect("should be parasable")
-                )*
+            match rebase.commit(None, &self.signature, None) {
+                Ok(new) => {
+                    rewritten.push((old, new));
+                    tip = new;
+                }
+                // The commit is already present in the new base
+                Err(err) if err.code() == ErrorCode::Applied => applied.push((old, tip)),
+                Err(err) => {
+                    rebase.abort().context("Cannot abort the rebase")?;
+                    return Err(anyhow::Error::new(err).context("Cannot commit"));
+                }
             }
-        };
-    }
+        }
+        rebase
+            .finish(Some(&self.signature))
+            .context("Cannot finish the rebase")?;
 
-    macro_rules! git {
-        ($self:ident, $($args:expr),* ) => {
-            execute_commands!("git", "-C", &$self.path(), $($args),*)
-        };
-    }
+        for (old, new) in &rewritten {
+            self.copy_note(*old, *new)?;
+        }
 
-    macro_rules! git_config {
-        ($self:ident, $($args:expr),* ) => {
-            git!($self, "config", "--local", $($args),*)
-        };
+        Ok(Rebased { rewritten, applied })
     }
 
-    struct GitTmp {
-        bare: Option<TempDir>,
-        directory: TempDir,
+    /// Create a lightweight tag on the given commit
+    ///
+    /// If force is true, an existing tag with the same name is replaced
+    pub fn create_tag(&self, name: &str, oid: Oid, force: bool) -> Result<()> {
+        let object = self
+            .repository
+            .find_object(oid, None)
+            .context("Cannot find commit")?;
+        self.repository
+            .tag_lightweight(name, &object, force)
+            .map(|_| ())
+            .context("Cannot create tag")
     }
 
-    impl Clone for GitTmp {
-        fn clone(&self) -> Self {
-            let clone = TempDir::new().expect("directory should be created");
-            let Some(ref bare) = self.bare else {
-                todo!("no bare repository: impossible to clone")
-            };
+    /// Create an annotated tag on the given commit, signed with the user's signature
+    ///
+    /// Nothing is done if the tag already exists with the same target and message,
+    /// otherwise if force is true, the existing tag is replaced
+    pub fn create_annotated_tag(
+        &self,
+        name: &str,
+        oid: Oid,
+        message: &str,
+        force: bool,
+    ) -> Result<()> {
+        let existing = self
+            .repository
+            .find_reference(&format!("refs/tags/{}", name))
+            .ok()
+            .and_then(|reference| reference.peel_to_tag().ok());
+        if let Some(tag) = existing {
+            if tag.target_id() == oid && tag.message() == Some(message) {
+                return Ok(());
+            }
+        }
 
-            execute_commands!(
-                "git",
-                "clone",
-                &format!("file://{}", bare.path().to_str().unwrap().to_string()),
-                &clone.path().to_str().unwrap().to_string()
-            );
+        let object = self
+            .repository
+            .find_object(oid, None)
+            .context("Cannot find commit")?;
+        self.repository
+            .tag(name, &object, &self.signature, message, force)
+            .map(|_| ())
+            .context("Cannot create tag")
+    }
 
-            let git = Self {
-                bare: None,
-                directory: clone,
-            };
+    /// Run `git rebase -i` on the stack with the given todo instead of opening the sequence editor
+    ///
+    /// Returns false when the rebase stopped, for instance because an `exec` line failed,
+    /// the rebase is then left in progress
+    pub fn rebase_with_todo(&self, todo: &str) -> Result<bool> {
+        let work_tree = self
+            .work_tree()
+            .context("cannot rebase in a bare repository")?;
+        let mut file = tempfile::Builder::new()
+            .prefix("yggit-")
+            .suffix(".todo")
+            .tempfile()
+            .context("cannot create the rebase todo")?;
+        file.write_all(todo.as_bytes())
+            .and_then(|()| file.flush())
+            .context("cannot write the rebase todo")?;
+        self.rebase_with_todo_file(work_tree, file.path())
+    }
 
-            git.init_config();
+    /// Run `git rebase -i` on the stack, the todo of git is replaced with the file
+    fn rebase_with_todo_file(&self, work_tree: &Path, todo_path: &Path) -> Result<bool> {
+        let mut rebase = std::process::Command::new("git");
+        rebase.current_dir(work_tree).args(["rebase", "-i"]);
+        // There is no base when the whole history is listed
+        if self.root {
+            rebase.arg("--root");
+        } else {
+            rebase.arg(self.base_commit()?.id().to_string());
+        }
+        // git runs the sequence editor with its own shell, on Windows too,
+        // the path is expanded by the shell instead of being quoted into the command
+        let status = rebase
+            .env("GIT_SEQUENCE_EDITOR", "cp \"$YGGIT_REBASE_TODO\"")
+            .env("YGGIT_REBASE_TODO", todo_path)
+            .status()
+            .context("cannot run git rebase")?;
+        Ok(status.success())
+    }
 
-            git
+    /// Abort the rebase in progress and restore HEAD, like `git rebase --abort`
+    pub fn abort_rebase(&self) -> Result<()> {
+        let work_tree = self
+            .work_tree()
+            .context("cannot rebase in a bare repository")?;
+        let status = std::process::Command::new("git")
+            .current_dir(work_tree)
+            .args(["rebase", "--abort"])
+            .status()
+            .context("cannot run git rebase --abort")?;
+        if !status.success() {
+            return Err(anyhow::Error::msg("git rebase --abort failed"));
         }
+        Ok(())
     }
 
-    /// Helper that execute git command
+    /// Let the user edit the content with the editor selected by yggit.ui and returns the edited content
     ///
-    /// So that git.rs can be tested against the git binary
-    impl GitTmp {
-        /// Create a repository with a bare one
-        fn init_bare(initial_branch: &str) -> Self {
-            let bare = tempfile::Builder::new()
-                .suffix(".git")
-                .tempdir()
-                .expect("git bare folder to be created");
-
-            execute_commands!(
-                "git",
-                "-C",
-                &bare.path().to_str().unwrap().to_string(),
-                "init",
-                "--initial-branch",
-                initial_branch,
-                "--bare"
-            );
-
-            // Then we clone it
-            let clone = TempDir::new().expect("Directory should be created");
+    /// The content is written in a new file of the temporary directory, removed once it has been read
+    pub fn edit(&self, content: &str) -> Result<String> {
+        let mut file = tempfile::Builder::new()
+            .prefix("yggit-")
+            .suffix(".yggit")
+            .tempfile()
+            .context("cannot create the file to edit")?;
+        file.write_all(content.as_bytes())
+            .and_then(|()| file.flush())
+            .context("cannot write file to disk")?;
+        // Windows editors cannot replace a file that is still open
+        let path = file.into_temp_path();
+        let file_path = path
+            .to_str()
+            .context("the path of the file to edit is not utf-8")?;
+        self.editor.edit(file_path)
+    }
+}
 
-            execute_commands!(
-                "git",
-                "clone",
-                &format!("file://{}", bare.path().to_str().unwrap().to_string()),
-                &clone.path().to_str().unwrap().to_string()
-            );
+#[cfg(test)]
+mod tests {
+    use git2::Oid;
+    use tempfile::TempDir;
 
-            let git = Self {
-                bare: Some(bare),
-                directory: clone,
-            };
+    use crate::{
+        core::PushStrategy,
+        git::{
+            tmp::{git, git_config, GitTmp},
+            BranchName, Origin,
+        },
+    };
 
-            git.init_config();
-
-            git
-        }
-
-        /// This function has to be called in each constructor
-        /// Later we can add an optional argument Config
-        fn init_config(&self) {
-            // TODO: put this in config.rs as dummy in test module
-            let config = GitConfig {
-                user: User {
-                    email: "example@example.com".to_string(),
-                    name: "Obi-wan".to_string(),
-                },
-                core: Core {
-                    editor: "theforce".to_string(), // The editor is not tested
-                },
-                yggit: Yggit {
-                    default_upstream: "origin".to_string(),
-                },
-            };
-
-            git_config!(self, "user.email", config.user.email.as_str());
-            git_config!(self, "user.name", config.user.name.as_str());
-            git_config!(self, "core.editor", config.core.editor.as_str());
-            git_config!(
-                self,
-                "yggit.defaultUpstream",
-                config.yggit.default_upstream.as_str()
-            );
-            git_config!(self, "notes.rewriteRef", "refs/notes/commits");
-        }
-
-        /// Add a file to the repository
-        fn new_file(&self, file_name: &str, content: &str) {
-            let path = self.directory.path().join(file_name);
-            let mut file = std::fs::File::create(path).expect("file should be created");
-            file.write_all(content.as_bytes())
-                .expect("should have written file to disk");
-        }
-
-        /// Add all files to the next commit
-        fn add_all(&self) {
-            let _ = git!(self, "add", ".");
-        }
-
-        /// Commit the change
-        fn commit(&self, commit_name: &str) -> Oid {
-            let _ = git!(self, "commit", "-m", commit_name);
-            let oid = git!(self, "rev-parse", "HEAD");
-            let oid = oid.trim();
-
-            Oid::from_str(&oid).unwrap()
-        }
-
-        fn add_note<N>(&self, oid: Oid, note: &N)
-        where
-            N: Serialize,
-        {
-            let json = serde_json::to_string(note).expect("note");
-            git!(self, "notes", "add", "-m", &json, &oid.to_string());
-        }
-
-        fn push(&self) {
-            git!(self, "push", "--force");
-        }
-
-        /// Returns the path of the repository
-        fn path(&self) -> String {
-            self.directory.path().to_str().unwrap().to_string()
-        }
-
-        /// Modifies the title of HEAD
-        fn amend(&self, title: &str) {
-            git!(self, "commit", "--amend", "-m", title);
-        }
-
-        /// pull the repository
-        fn pull(&self) {
-            git!(self, "pull");
-        }
-
-        fn create_branch(&self, branch_name: &str) {
-            git!(self, "checkout", "-b", branch_name);
-        }
-    }
+    use super::{
+        authenticate, authenticator, credentials_error, with_token, DiffStat, FetchFailed, Git,
+        NoHttpsCredentials, PushMode, PushOutcome, RefStatus, SshAuthenticationFailed,
+        TrackingStatus,
+    };
+    use git2::{CredentialType, Error, ErrorClass, ErrorCode};
+    use std::{io::Write, path::Path};
 
     #[test]
     fn test_open_repository() {
@@ -584,12 +2072,33 @@ mod tests {
         (oid, repo)
     }
 
+    #[test]
+    fn test_open_with_git_dir() {
+        let (head, repo) = init_repo_with_commit();
+        let elsewhere = TempDir::new().unwrap();
+        let git_dir = repo.directory.path().join(".git");
+
+        let git = Git::open_with_env(
+            elsewhere.path().to_str().unwrap(),
+            Some(git_dir.into_os_string()),
+            Some(repo.directory.path().as_os_str().to_os_string()),
+        )
+        .expect("GIT_DIR should be opened");
+        let commit = git
+            .find_commit::<String>(head)
+            .unwrap()
+            .expect("commit should be present");
+        assert_eq!(commit.title, "first commit");
+        assert!(Git::open_with_env(elsewhere.path().to_str().unwrap(), None, None).is_err());
+    }
+
     #[test]
     fn test_find_commit() {
         let (head, repo) = init_repo_with_commit();
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
         let commit = git
             .find_commit::<String>(head)
+            .unwrap()
             .expect("commit should be present");
         assert_eq!(commit.title, "first commit");
     }
@@ -598,7 +2107,7 @@ mod tests {
     fn test_commit_not_found() {
         let (_, repo) = init_repo_with_commit();
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
-        let commit = git.find_commit::<String>(Oid::zero());
+        let commit = git.find_commit::<String>(Oid::zero()).unwrap();
         assert!(commit.is_none())
     }
 
@@ -608,6 +2117,7 @@ mod tests {
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
         let note = git
             .find_note::<String>(head)
+            .unwrap()
             .expect("the note has to be present");
         assert_eq!(note, "my super note");
     }
@@ -616,20 +2126,32 @@ mod tests {
     fn test_get_no_note() {
         let (_, repo) = init_repo_with_commit();
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
-        let note = git.find_note::<String>(Oid::zero());
+        let note = git.find_note::<String>(Oid::zero()).unwrap();
         assert!(note.is_none());
     }
 
+    #[test]
+    fn test_broken_notes_ref() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).expect("should be able to open the repository");
+        // The notes reference does not contain an oid anymore
+        std::fs::write(git.git_dir().join("refs/notes/commits"), "corrupted\n").unwrap();
+        let note = git.find_note::<String>(head);
+        assert!(note.is_err());
+        assert!(git.find_commit::<String>(head).is_err());
+    }
+
     #[test]
     fn test_delete_note() {
         let (head, repo) = init_repo_with_commit();
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
         let note = git
             .find_note::<String>(head)
+            .unwrap()
             .expect("the note has to be present");
         assert_eq!(note, "my super note");
         git.delete_note(&head).expect("not should be deleted");
-        let note = git.find_note::<String>(head);
+        let note = git.find_note::<String>(head).unwrap();
         assert!(note.is_none())
     }
 
@@ -637,9 +2159,16 @@ mod tests {
     fn test_set_note() {
         let (head, repo) = init_repo_with_commit();
         let git = Git::open(&repo.path()).expect("should be able to open the repository");
-        git.set_note(head, "a note").expect("not should be written");
+        let note_oid = git.set_note(head, "a note").expect("not should be written");
+        assert!(!note_oid.is_zero());
+        let written = git
+            .repository
+            .find_note(Some(&git.config.yggit.notes_ref), head)
+            .unwrap();
+        assert_eq!(written.id(), note_oid);
         let note = git
             .find_note::<String>(head)
+            .unwrap()
             .expect("the note has to be present");
         assert_eq!(note, "a note");
     }
@@ -655,6 +2184,7 @@ mod tests {
 
         let note = git
             .find_note::<String>(head)
+            .unwrap()
             .expect("the note has to be present");
 
         assert_eq!(note, "a note 2");
@@ -704,10 +2234,56 @@ mod tests {
         // it will delete the history of clone
         repo.push();
         // the push force with lease should be refused because the origin has divered
-        let result = git.push_force_with_lease("origin", "main");
+        let result = git.push_force_with_lease(&Origin::from("origin"), &BranchName::from("main"));
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_dry_push_diverged() {
+        let repo = GitTmp::init_bare("main");
+        let clone = repo.clone();
+
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+
+        let git = Git::open(&clone.path()).expect("git should be open");
+        clone.new_file("yolo.md", "some content");
+        clone.add_all();
+        clone.commit("my first commit");
+        clone.push();
+
+        // The remote is rewritten by someone else
+        repo.push();
+        let remote_head = git!(repo, "rev-parse", "origin/main");
+
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        let head = Oid::from_str(git!(clone, "rev-parse", "HEAD").trim()).unwrap();
+        let outcome = git
+            .dry_push(&origin, &main, head, PushStrategy::Lease)
+            .unwrap();
+        assert_eq!(outcome, PushOutcome::Diverged);
+        let outcome = git
+            .dry_push(&origin, &main, head, PushStrategy::Force)
+            .unwrap();
+        assert_eq!(outcome, PushOutcome::Update);
+        // A normal push never rewinds the remote, even once it has been fetched
+        git!(clone, "fetch");
+        let outcome = git
+            .dry_push(&origin, &main, head, PushStrategy::Normal)
+            .unwrap();
+        assert_eq!(outcome, PushOutcome::Diverged);
+        let outcome = git
+            .dry_push(&origin, &main, head, PushStrategy::Lease)
+            .unwrap();
+        assert_eq!(outcome, PushOutcome::Update);
+
+        // The remote has not changed
+        git!(repo, "fetch");
+        assert_eq!(git!(repo, "rev-parse", "origin/main"), remote_head);
+    }
+
     #[test]
     fn test_push_force_with_lease_accepted() {
         let repo = GitTmp::init_bare("main");
@@ -740,10 +2316,73 @@ mod tests {
         clone.add_all();
         clone.commit("new commit");
         // the two origins matched, so we can erase the history
-        let result = git.push_force_with_lease("origin", "main");
+        let result = git.push_force_with_lease(&Origin::from("origin"), &BranchName::from("main"));
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_push_force_with_lease_expecting() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let first = repo.commit("first commit");
+        repo.push();
+        let clone = repo.clone();
+
+        // The remote moves, clone has not fetched
+        repo.amend("hello there");
+        repo.push();
+        let remote = git!(repo, "rev-parse", "HEAD");
+        let remote = Oid::from_str(remote.trim()).unwrap();
+
+        clone.new_file("yolo.md", "some content");
+        clone.add_all();
+        clone.commit("my first commit");
+
+        let git = Git::open(&clone.path()).expect("git should be open");
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        assert!(git.push_force_with_lease(&origin, &main).is_err());
+        assert!(git
+            .push_force_with_lease_expecting(&origin, &main, first)
+            .is_err());
+        git.push_force_with_lease_expecting(&origin, &main, remote)
+            .expect("the remote is on the expected commit");
+        let pushed = git!(clone, "ls-remote", "origin", "refs/heads/main");
+        assert!(pushed.starts_with(&git!(clone, "rev-parse", "HEAD").trim().to_string()));
+    }
+
+    #[test]
+    fn test_push_normal() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+
+        let git = Git::open(&repo.path()).expect("git should be open");
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        repo.new_file("kenobi.md", "General Kenobi\n");
+        repo.add_all();
+        repo.commit("second commit");
+        assert_eq!(
+            git.push_normal(&origin, &main).unwrap(),
+            PushOutcome::Update
+        );
+        assert_eq!(
+            git.push_normal(&origin, &main).unwrap(),
+            PushOutcome::UpToDate
+        );
+
+        // The remote is never rewound
+        repo.amend("general kenobi");
+        assert!(git.push_normal(&origin, &main).is_err());
+        let pushed = git!(repo, "ls-remote", "origin", "refs/heads/main");
+        let head = git!(repo, "rev-parse", "HEAD");
+        assert!(!pushed.starts_with(head.trim()));
+    }
+
     #[test]
     fn test_push_force() {
         let repo = GitTmp::init_bare("main");
@@ -779,10 +2418,150 @@ mod tests {
         repo.push();
         // This test is based on the push_force_with_lease one
         // where push --force-with-lease fails, push --force has to work
-        let result = git.push_force("origin", "main");
+        let result = git.push_force(&Origin::from("origin"), &BranchName::from("main"));
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_push_force_without_remote_tracking_branch() {
+        let repo = GitTmp::init_bare("main");
+        let clone = repo.clone();
+
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+
+        clone.new_file("yolo.md", "some content");
+        clone.add_all();
+        let commit = clone.commit("my first commit");
+        // The branch exists on the remote, but has never been fetched
+        let git = Git::open(&clone.path()).expect("git should be open");
+        assert!(!git.remote_branch_exists(&Origin::from("origin"), &BranchName::from("main")));
+
+        let outcome = git
+            .push_force(&Origin::from("origin"), &BranchName::from("main"))
+            .expect("the push should be forced again");
+        assert_eq!(outcome, PushOutcome::Update);
+        let remote = git!(clone, "ls-remote", "origin", "refs/heads/main");
+        assert!(remote.starts_with(&commit.to_string()));
+    }
+
+    #[test]
+    fn test_authenticate_git_command() {
+        let mut fill = std::process::Command::new("git");
+        authenticate(
+            &mut fill,
+            Some("a secret token"),
+            Some(Path::new("/keys/it's")),
+        );
+        let output = fill
+            .args(["credential", "fill"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(b"protocol=https\nhost=example.com\n\n")?;
+                child.wait_with_output()
+            })
+            .expect("git credential should run");
+        let credentials = String::from_utf8(output.stdout).unwrap();
+        assert!(credentials.contains("username=x-access-token\n"));
+        assert!(credentials.contains("password=a secret token\n"));
+        // The token is never part of the command line
+        assert!(fill
+            .get_args()
+            .all(|arg| !arg.to_string_lossy().contains("secret")));
+
+        let ssh_command = fill
+            .get_envs()
+            .find(|(key, _)| *key == "GIT_SSH_COMMAND")
+            .and_then(|(_, value)| value);
+        assert_eq!(
+            ssh_command,
+            Some(std::ffi::OsStr::new(
+                "ssh -i \"$YGGIT_SSH_KEY\" -o IdentitiesOnly=yes"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_push_atomic() {
+        let repo = GitTmp::init_bare("main");
+        let clone = repo.clone();
+
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        clone.pull();
+
+        clone.new_file("yolo.md", "some content");
+        clone.add_all();
+        clone.commit("my first commit");
+        git!(clone, "branch", "feature");
+        // The remote is rewritten by someone else
+        repo.amend("rewritten commit");
+        repo.push();
+        let remote_main = git!(repo, "ls-remote", "origin", "refs/heads/main");
+
+        let git = Git::open(&clone.path()).expect("git should be open");
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        let feature = BranchName::from("feature");
+
+        // The lease of main does not hold, so feature is not created either
+        let outcomes = git
+            .push_atomic(
+                &origin,
+                &[
+                    (main.clone(), PushStrategy::Lease),
+                    (feature.clone(), PushStrategy::Lease),
+                ],
+            )
+            .unwrap()
+            .expect("the remote supports atomic pushes");
+        assert!(outcomes.iter().all(Result::is_err));
+        assert_eq!(
+            git!(repo, "ls-remote", "origin", "refs/heads/main"),
+            remote_main
+        );
+        assert!(git!(repo, "ls-remote", "origin", "refs/heads/feature").is_empty());
+
+        let outcomes = git
+            .push_atomic(
+                &origin,
+                &[
+                    (main.clone(), PushStrategy::Force),
+                    (feature.clone(), PushStrategy::Lease),
+                ],
+            )
+            .unwrap()
+            .expect("the remote supports atomic pushes");
+        let outcomes: Vec<PushOutcome> = outcomes.into_iter().map(Result::unwrap).collect();
+        assert_eq!(outcomes, vec![PushOutcome::Update, PushOutcome::New]);
+        let head = git!(clone, "rev-parse", "HEAD");
+        let remote_heads = git!(
+            repo,
+            "ls-remote",
+            "origin",
+            "refs/heads/main",
+            "refs/heads/feature"
+        );
+        assert_eq!(remote_heads.matches(head.trim()).count(), 2);
+
+        // Nothing is pushed by a remote that cannot push atomically
+        repo.bare_config("receive.advertiseAtomic", "false");
+        let outcomes = git
+            .push_atomic(&origin, &[(main, PushStrategy::Force)])
+            .unwrap();
+        assert!(outcomes.is_none());
+    }
+
     // Testing `main_branch`
 
     /// Initializes a repository with a main branch
@@ -816,6 +2595,47 @@ mod tests {
         assert_eq!(branch, "master");
     }
 
+    #[test]
+    fn test_default_branch_is_cached() {
+        let repo = init_main_branch_test("master");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.default_branch().unwrap(), "master");
+        // main is created afterwards, the detected branch does not change
+        git!(repo, "branch", "main");
+        assert_eq!(git.default_branch().unwrap(), "master");
+        assert_eq!(git.default_branch().unwrap(), "master");
+        // A new instance detects it
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.default_branch().unwrap(), "main");
+    }
+
+    #[test]
+    fn test_find_master_without_remote_head() {
+        let repo = init_main_branch_test("master");
+        git!(repo, "remote", "set-head", "origin", "--delete");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.default_branch().unwrap(), "master");
+    }
+
+    #[test]
+    fn test_configured_default_branch() {
+        let repo = init_main_branch_test("main");
+        git_config!(repo, "yggit.defaultBranch", "develop");
+        // develop does not exist yet
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.default_branch().unwrap(), "main");
+
+        git!(repo, "branch", "develop");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.default_branch().unwrap(), "develop");
+
+        let repo = init_main_branch_test("unknown");
+        git_config!(repo, "yggit.defaultBranch", "develop");
+        let git = Git::open(&repo.path()).unwrap();
+        let error = git.default_branch().unwrap_err();
+        assert!(error.to_string().contains("yggit.defaultBranch"));
+    }
+
     #[test]
     fn test_find_unknown_branch() {
         let repo = init_main_branch_test("unknown");
@@ -843,10 +2663,1064 @@ mod tests {
         let git = Git::open(&repo.path()).unwrap();
         let commits = git.list_commits::<String>().unwrap();
         assert_eq!(commits.len(), 1);
-        let commit = commits.iter().next().unwrap();
+        let commit = commits.first().unwrap();
         assert_eq!(commit.id, oid);
         assert_eq!(commit.note, None);
         assert_eq!(commit.title, "first commit on my branch");
-        assert_eq!(commit.description, Some("".to_string())); // TODO: empty string should not be allowed
+        assert_eq!(commit.description, None);
+
+        repo.amend("title\n\nbody of the commit\n");
+        let git = Git::open(&repo.path()).unwrap();
+        let commits = git.list_commits::<String>().unwrap();
+        assert_eq!(
+            commits.first().unwrap().description.as_deref(),
+            Some("body of the commit")
+        );
+    }
+
+    #[test]
+    fn test_is_shallow() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.new_file("other.md", "General Kenobi\n");
+        repo.add_all();
+        repo.commit("second commit");
+        repo.push();
+
+        let git = Git::open(&repo.path()).unwrap();
+        assert!(!git.is_shallow());
+
+        let shallow = repo.shallow_clone(1);
+        let git = Git::open(&shallow.path()).unwrap();
+        assert!(git.is_shallow());
+    }
+
+    #[test]
+    fn test_set_note_keeps_pretty_format() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct PrettyNote {
+            name: String,
+            count: u32,
+        }
+
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let oid = repo.commit("first commit");
+        let pretty = serde_json::to_string_pretty(&PrettyNote {
+            name: "kenobi".to_string(),
+            count: 1,
+        })
+        .unwrap();
+        git!(repo, "notes", "add", "-m", &pretty, &oid.to_string());
+
+        let git = Git::open(&repo.path()).unwrap();
+        let note = PrettyNote {
+            name: "kenobi".to_string(),
+            count: 2,
+        };
+        git.set_note(oid, &note).expect("note should be written");
+
+        let expected = format!("{}\n", serde_json::to_string_pretty(&note).unwrap());
+        assert_eq!(repo.show_note(oid), expected);
+        assert_eq!(git.find_note::<PrettyNote>(oid).unwrap(), Some(note));
+    }
+
+    #[test]
+    fn test_set_note_compact_by_default() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        git.delete_note(&head).unwrap();
+        git.set_note(head, ["a", "b"])
+            .expect("note should be written");
+        assert_eq!(repo.show_note(head), "[\"a\",\"b\"]");
+    }
+
+    #[test]
+    fn test_custom_notes_ref() {
+        let (head, repo) = init_repo_with_commit();
+        git_config!(repo, "yggit.notesRef", "refs/notes/yggit");
+        git_config!(repo, "notes.rewriteRef", "refs/notes/yggit");
+
+        let git = Git::open(&repo.path()).unwrap();
+        // The note of the default ref is not visible anymore
+        assert!(git.find_note::<String>(head).unwrap().is_none());
+
+        git.set_note(head, "a note")
+            .expect("note should be written");
+        let note = git
+            .find_note::<String>(head)
+            .unwrap()
+            .expect("the note has to be present");
+        assert_eq!(note, "a note");
+
+        let raw = git!(
+            repo,
+            "notes",
+            "--ref",
+            "refs/notes/yggit",
+            "show",
+            &head.to_string()
+        );
+        assert_eq!(raw, "\"a note\"");
+        let raw = git!(repo, "notes", "show", &head.to_string());
+        assert_eq!(raw, "\"my super note\"\n");
+
+        git.delete_note(&head).expect("note should be deleted");
+        assert!(git.find_note::<String>(head).unwrap().is_none());
+    }
+
+    /// helper that initialize a repository where main is not an ancestor of HEAD
+    fn init_repo_with_moved_main() -> GitTmp {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        repo.new_file("hey", "hey");
+        repo.add_all();
+        repo.commit("first commit on my branch");
+        repo.checkout("main");
+        repo.new_file("main", "main");
+        repo.add_all();
+        repo.commit("new commit on main");
+        repo.checkout("test");
+        repo
+    }
+
+    #[test]
+    fn test_list_commits_main_not_ancestor() {
+        let repo = init_repo_with_moved_main();
+        let git = Git::open(&repo.path()).unwrap();
+        // The stack starts where the branch forked from main, like main..HEAD
+        let commits = git.list_commits::<String>().unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].title, "first commit on my branch");
+
+        let head = Oid::from_str(git!(repo, "rev-parse", "HEAD").trim()).unwrap();
+        let main = Oid::from_str(git!(repo, "rev-parse", "main").trim()).unwrap();
+        let fork = Oid::from_str(git!(repo, "rev-parse", "main~1").trim()).unwrap();
+        assert_eq!(git.merge_base(head, main).unwrap(), fork);
+        assert_eq!(commits[0].parents, vec![fork]);
+    }
+
+    #[test]
+    fn test_list_commits_assume_unchanged_base() {
+        let repo = init_repo_with_moved_main();
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.assume_unchanged_base(true);
+        let commits = git
+            .list_commits::<String>()
+            .expect("the ancestor check should be skipped");
+        // The main commit is never met so the whole history is listed
+        assert_eq!(commits.len(), 2);
+    }
+
+    #[test]
+    fn test_create_tag() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        git.create_tag("v1", head, false)
+            .expect("tag should be created");
+
+        let oid = git!(repo, "rev-parse", "v1");
+        assert_eq!(oid.trim(), head.to_string());
+        let kind = git!(repo, "cat-file", "-t", "v1");
+        assert_eq!(kind.trim(), "commit");
+    }
+
+    #[test]
+    fn test_create_annotated_tag() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        git.create_annotated_tag("v1", head, "release candidate", true)
+            .expect("tag should be created");
+        let tag = git!(repo, "rev-parse", "v1");
+
+        let kind = git!(repo, "cat-file", "-t", "v1");
+        assert_eq!(kind.trim(), "tag");
+        let oid = git!(repo, "rev-parse", "v1^{commit}");
+        assert_eq!(oid.trim(), head.to_string());
+        let message = git!(repo, "tag", "-l", "--format=%(contents)", "v1");
+        assert_eq!(message.trim(), "release candidate");
+
+        // Creating the same tag again does not create a new tag object
+        git.create_annotated_tag("v1", head, "release candidate", true)
+            .expect("tag should be kept");
+        assert_eq!(git!(repo, "rev-parse", "v1"), tag);
+    }
+
+    #[test]
+    fn test_list_commits_onto_current_branch() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        repo.new_file("hey", "hey");
+        repo.add_all();
+        repo.commit("first commit on my branch");
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_onto(Some("test".to_string()));
+        let result = git.list_commits::<String>();
+        let Err(err) = result else {
+            panic!("the current branch cannot be the base")
+        };
+        assert_eq!(
+            err.to_string(),
+            "cannot use the current branch test as the base of the stack, the base has to be one of its ancestors"
+        );
+    }
+
+    #[test]
+    fn test_list_commits_onto_other_branch() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("base");
+        repo.new_file("hey", "hey");
+        repo.add_all();
+        repo.commit("first commit on base");
+        repo.create_branch("test");
+        repo.new_file("hello", "hello");
+        repo.add_all();
+        let oid = repo.commit("first commit on test");
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_onto(Some("base".to_string()));
+        let commits = git.list_commits::<String>().unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].id, oid);
+    }
+
+    #[test]
+    fn test_list_commits_onto_remote_branch() {
+        let repo = GitTmp::init_bare("main");
+        let clone = repo.clone();
+        clone.new_file("readme.md", "Hello there\n");
+        clone.add_all();
+        clone.commit("first commit");
+        clone.push();
+        clone.create_branch("stack");
+        clone.new_file("feature.md", "General Kenobi\n");
+        clone.add_all();
+        let oid = clone.commit("feature commit");
+        git!(clone, "branch", "-D", "main");
+
+        let mut git = Git::open(&clone.path()).unwrap();
+        git.set_onto(Some("origin/main".to_string()));
+        let commits = git.list_commits::<String>().unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].id, oid);
+    }
+
+    #[test]
+    fn test_list_commits_onto_commit_or_tag() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        repo.new_file("hey", "hey");
+        repo.add_all();
+        let base = repo.commit("first commit on test");
+        git!(repo, "tag", "v1");
+        repo.new_file("hello", "hello");
+        repo.add_all();
+        let oid = repo.commit("second commit on test");
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        for onto in [
+            base.to_string(),
+            base.to_string()[..7].to_string(),
+            "v1".to_string(),
+        ] {
+            git.set_onto(Some(onto));
+            let commits = git.list_commits::<String>().unwrap();
+            assert_eq!(commits.len(), 1);
+            assert_eq!(commits[0].id, oid);
+        }
+        let commits = git.list_commits_until_oid::<String>(base).unwrap();
+        assert_eq!(commits.len(), 1);
+
+        git.set_onto(Some("unknown".to_string()));
+        let Err(err) = git.list_commits::<String>() else {
+            panic!("unknown is not a base")
+        };
+        assert_eq!(
+            err.to_string(),
+            "unknown is neither a commit, a branch nor a tag"
+        );
+
+        // The base has to be an ancestor of HEAD
+        repo.checkout("main");
+        repo.new_file("other", "other");
+        repo.add_all();
+        let other = repo.commit("commit on main");
+        repo.checkout("test");
+        assert!(git.list_commits_until_oid::<String>(other).is_err());
+    }
+
+    #[test]
+    fn test_override_identity() {
+        let (head, repo) = init_repo_with_commit();
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.override_identity(Some("R2-D2"), None)
+            .expect("identity should be overridden");
+        git.set_note(head, "a note")
+            .expect("note should be written");
+
+        let author = git!(
+            repo,
+            "log",
+            "-1",
+            "--format=%an <%ae>",
+            "refs/notes/commits"
+        );
+        assert_eq!(author.trim(), "R2-D2 <example@example.com>");
+
+        git.override_identity(None, Some("r2d2@example.com"))
+            .expect("identity should be overridden");
+        git.set_note(head, "another note")
+            .expect("note should be written");
+
+        let author = git!(
+            repo,
+            "log",
+            "-1",
+            "--format=%an <%ae>",
+            "refs/notes/commits"
+        );
+        assert_eq!(author.trim(), "Obi-wan <r2d2@example.com>");
+    }
+
+    #[test]
+    fn test_push_reports_ref_status() {
+        let (_, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let statuses = git
+            .push(
+                &Origin::from("origin"),
+                &BranchName::from("main"),
+                PushMode::Force,
+                None,
+            )
+            .map(|(_, statuses)| statuses)
+            .expect("should be pushed");
+        assert_eq!(
+            statuses,
+            vec![RefStatus {
+                refname: "refs/heads/main".to_string(),
+                error: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_push_reports_rejected_ref() {
+        let (_, repo) = init_repo_with_commit();
+        repo.push();
+        repo.lock_bare_ref("refs/heads/main");
+        repo.amend("rewritten commit");
+
+        let git = Git::open(&repo.path()).unwrap();
+        let result = git.push_force(&Origin::from("origin"), &BranchName::from("main"));
+        let Err(err) = result else {
+            panic!("the remote should reject the push")
+        };
+        assert!(err
+            .to_string()
+            .starts_with("remote rejected refs/heads/main"));
+    }
+
+    #[test]
+    fn test_push_fails_after_negotiation() {
+        let (_, repo) = init_repo_with_commit();
+        repo.break_bare_packs();
+
+        // The negotiation accepts the new branch, then the objects cannot be written
+        let git = Git::open(&repo.path()).unwrap();
+        let result = git.push_force_with_lease(&Origin::from("origin"), &BranchName::from("main"));
+        let Err(err) = result else {
+            panic!("the push should fail")
+        };
+        assert!(err.to_string().starts_with("cannot push origin:main"));
+
+        repo.repair_bare_packs();
+        let outcome = git
+            .push_force_with_lease(&Origin::from("origin"), &BranchName::from("main"))
+            .expect("should be pushed");
+        assert_eq!(outcome, PushOutcome::New);
+    }
+
+    #[test]
+    fn test_rebase_onto() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("other");
+        repo.new_file("other", "other");
+        repo.add_all();
+        let other = repo.commit("commit on other");
+        repo.checkout("main");
+        repo.create_branch("feature");
+        repo.new_file("first", "first");
+        repo.add_all();
+        let first = repo.commit("first commit on feature");
+        repo.new_file("second", "second");
+        repo.add_all();
+        let second = repo.commit("second commit on feature");
+        repo.add_note(second, &"a note".to_string());
+
+        let git = Git::open(&repo.path()).unwrap();
+        let rewritten = git
+            .rebase_onto("other")
+            .expect("should be rebased")
+            .rewritten;
+        assert_eq!(rewritten.len(), 2);
+        assert_eq!(rewritten[0].0, first);
+        assert_eq!(rewritten[1].0, second);
+
+        let head = git!(repo, "rev-parse", "HEAD");
+        assert_eq!(head.trim(), rewritten[1].1.to_string());
+        let base = git!(repo, "rev-parse", "HEAD~2");
+        assert_eq!(base.trim(), other.to_string());
+        let branch = git!(repo, "branch", "--show-current");
+        assert_eq!(branch.trim(), "feature");
+
+        let note = git
+            .find_note::<String>(rewritten[1].1)
+            .unwrap()
+            .expect("the note has to be copied");
+        assert_eq!(note, "a note");
+    }
+
+    #[test]
+    fn test_rebase_with_todo_file() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("feature");
+        let [first, second] = ["first", "second"].map(|name| {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name)
+        });
+
+        // The path is never interpreted by the shell
+        let directory = TempDir::new().unwrap();
+        let todo_path = directory.path().join("it's $HOME `todo`");
+        std::fs::write(&todo_path, format!("pick {second}\npick {first}\n")).unwrap();
+        let git = Git::open(&repo.path()).unwrap();
+        let work_tree = git.work_tree().unwrap().to_path_buf();
+        assert!(git
+            .rebase_with_todo_file(&work_tree, &todo_path)
+            .expect("git should run"));
+        let titles = git!(repo, "log", "--format=%s", "-2");
+        assert_eq!(titles, "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_write_invalidates_cached_note() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(
+            git.find_note::<String>(head).unwrap().unwrap(),
+            "my super note"
+        );
+
+        // The note is now cached
+        git.set_note(head, "a note")
+            .expect("note should be written");
+        assert_eq!(git.find_note::<String>(head).unwrap().unwrap(), "a note");
+
+        git.delete_note(&head).expect("note should be deleted");
+        assert!(git.find_note::<String>(head).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sign_notes() {
+        let (head, repo) = init_repo_with_commit();
+        let keys = TempDir::new().unwrap();
+        let key = keys.path().join("id_ed25519");
+        let key = key.to_str().unwrap();
+        let output = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f", key])
+            .output()
+            .expect("ssh-keygen should run");
+        assert!(output.status.success());
+        git_config!(repo, "commit.gpgSign", "true");
+        git_config!(repo, "gpg.format", "ssh");
+        git_config!(repo, "user.signingKey", key);
+
+        let git = Git::open(&repo.path()).unwrap();
+        git.set_note(head, "a signed note")
+            .expect("note should be written");
+        let notes = git.repository.refname_to_id("refs/notes/commits").unwrap();
+        let (signature, _) = git
+            .repository
+            .extract_signature(&notes, None)
+            .expect("the notes commit should be signed");
+        assert!(signature
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN SSH SIGNATURE-----"));
+
+        // The note is written unsigned when the signature fails
+        git_config!(repo, "gpg.ssh.program", "false");
+        let git = Git::open(&repo.path()).unwrap();
+        git.set_note(head, "an unsigned note")
+            .expect("note should be written");
+        let notes = git.repository.refname_to_id("refs/notes/commits").unwrap();
+        assert!(git.repository.extract_signature(&notes, None).is_err());
+        assert_eq!(
+            git.find_note::<String>(head).unwrap().unwrap(),
+            "an unsigned note"
+        );
+    }
+
+    #[test]
+    fn test_branch_checked_out_elsewhere() {
+        let (head, repo) = init_repo_with_commit();
+        let worktree = TempDir::new().unwrap();
+        let worktree_path = worktree.path().join("other");
+        git!(
+            repo,
+            "worktree",
+            "add",
+            "-b",
+            "other",
+            worktree_path.to_str().unwrap()
+        );
+
+        let git = Git::open(&repo.path()).unwrap();
+        let path = git
+            .branch_checked_out_elsewhere(&BranchName::from("other"))
+            .unwrap()
+            .expect("other is checked out in a worktree");
+        assert_eq!(
+            path.canonicalize().unwrap(),
+            worktree_path.canonicalize().unwrap()
+        );
+        assert!(git
+            .branch_checked_out_elsewhere(&BranchName::from("feature"))
+            .unwrap()
+            .is_none());
+
+        let result = git.set_branch_to_commit(&BranchName::from("other"), head, None);
+        assert!(result.is_err());
+        git.set_branch_to_commit(&BranchName::from("feature"), head, None)
+            .expect("feature is not checked out");
+    }
+
+    #[test]
+    fn test_push_branch_with_the_name_of_a_tag() {
+        let (_, repo) = init_repo_with_commit();
+        repo.push();
+        git!(repo, "tag", "feature");
+        git!(repo, "push", "origin", "refs/tags/feature");
+        repo.create_branch("feature");
+
+        // Git accepts both, the tag is left untouched
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+        git.push_force_with_lease(&origin, &BranchName::from("feature"))
+            .expect("the branch should be pushed");
+        let refs = git.remote_refs(&origin).unwrap();
+        assert!(refs.contains(&"refs/heads/feature".to_string()));
+        assert!(refs.contains(&"refs/tags/feature".to_string()));
+    }
+
+    #[test]
+    fn test_push_to_anonymous_remote() {
+        let (head, repo) = init_repo_with_commit();
+        let other = TempDir::new().unwrap();
+        git2::Repository::init_bare(other.path()).unwrap();
+        let url = format!("file://{}", other.path().display());
+
+        let git = Git::open(&repo.path()).unwrap();
+        git.push_force(&Origin::from(url.as_str()), &BranchName::from("main"))
+            .expect("should be pushed to the url");
+
+        let other = git2::Repository::open_bare(other.path()).unwrap();
+        let pushed = other.find_reference("refs/heads/main").unwrap().target();
+        assert_eq!(pushed, Some(head));
+    }
+
+    #[test]
+    fn test_remembered_onto_per_branch() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("feature-a");
+        repo.new_file("a", "a");
+        repo.add_all();
+        repo.commit("a");
+        let mut git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.remembered_onto(), None);
+        git.set_onto(Some("main".to_string()));
+        git.remember_onto().unwrap();
+
+        repo.create_branch("feature-b");
+        repo.new_file("b", "b");
+        repo.add_all();
+        repo.commit("b");
+        git.set_onto(Some("feature-a".to_string()));
+        git.remember_onto().unwrap();
+        assert_eq!(git.remembered_onto(), Some("feature-a".to_string()));
+
+        // A typo or the checked out branch are never remembered
+        for onto in ["mian", "feature-b"] {
+            git.set_onto(Some(onto.to_string()));
+            assert!(git.remember_onto().is_err(), "{onto}");
+            assert_eq!(git.remembered_onto(), Some("feature-a".to_string()));
+        }
+
+        repo.checkout("feature-a");
+        assert_eq!(git.remembered_onto(), Some("main".to_string()));
+        repo.checkout("feature-b");
+        assert_eq!(git.remembered_onto(), Some("feature-a".to_string()));
+    }
+
+    #[test]
+    fn test_list_commits_max_stack_size() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        for name in ["first", "second", "third"] {
+            repo.new_file(name, name);
+            repo.add_all();
+            repo.commit(name);
+        }
+        git_config!(repo, "yggit.maxStackSize", "3");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.list_commits::<String>().unwrap().len(), 3);
+
+        git_config!(repo, "yggit.maxStackSize", "2");
+        let git = Git::open(&repo.path()).unwrap();
+        let Err(error) = git.list_commits::<String>() else {
+            panic!("the stack should be too big")
+        };
+        assert!(error
+            .to_string()
+            .starts_with("the stack has more than 2 commits"));
+    }
+
+    #[test]
+    fn test_list_commits_from_root() {
+        let (first, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        repo.new_file("second", "second");
+        repo.add_all();
+        let second = repo.commit("second commit");
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        let commits = git.list_commits::<String>().unwrap();
+        assert_eq!(commits.len(), 1);
+
+        git.include_root(true);
+        let commits = git.list_commits::<String>().unwrap();
+        let ids: Vec<Oid> = commits.iter().map(|commit| commit.id).collect();
+        assert_eq!(ids, vec![first, second]);
+        assert!(git.rebase_onto("main").is_err());
+    }
+
+    #[test]
+    fn test_set_branch_to_commit_with_upstream() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+
+        let previous = git
+            .set_branch_to_commit(&BranchName::from("feature"), head, Some(&origin))
+            .unwrap();
+        assert_eq!(previous, None);
+        let remote = git_config!(repo, "branch.feature.remote");
+        assert_eq!(remote.trim(), "origin");
+        let merge = git_config!(repo, "branch.feature.merge");
+        assert_eq!(merge.trim(), "refs/heads/feature");
+
+        // The upstream of an existing branch is not changed
+        git!(repo, "branch", "existing");
+        git.set_branch_to_commit(&BranchName::from("existing"), head, Some(&origin))
+            .unwrap();
+        let config = git.repository.config().unwrap();
+        assert!(config.get_string("branch.existing.remote").is_err());
+    }
+
+    #[test]
+    fn test_list_commits_since_tag() {
+        let (_, repo) = init_repo_with_commit();
+        git!(repo, "tag", "v1");
+        repo.new_file("second", "second");
+        repo.add_all();
+        repo.commit("second commit");
+        git!(repo, "tag", "-a", "v2", "-m", "release");
+        repo.new_file("third", "third");
+        repo.add_all();
+        let third = repo.commit("third commit");
+        git!(repo, "tag", "nightly");
+        repo.new_file("fourth", "fourth");
+        repo.add_all();
+        let fourth = repo.commit("fourth commit");
+
+        let mut git = Git::open(&repo.path()).unwrap();
+        git.set_since_tag(Some("v*".to_string()));
+        let commits = git.list_commits::<String>().unwrap();
+        let ids: Vec<Oid> = commits.iter().map(|commit| commit.id).collect();
+        assert_eq!(ids, vec![third, fourth]);
+
+        git.set_since_tag(Some("release-*".to_string()));
+        assert!(git.list_commits::<String>().is_err());
+    }
+
+    #[test]
+    fn test_diff_stat_of_moved_branch() {
+        let (first, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let feature = BranchName::from("feature");
+        let previous = git.set_branch_to_commit(&feature, first, None).unwrap();
+        assert_eq!(previous, None);
+
+        repo.new_file(
+            "readme.md",
+            concat!("# Star wars", "\n", "Hello there\n", "You are a bold one\n"),
+        );
+        repo.new_file("other.md", "other\n");
+        repo.add_all();
+        let second = repo.commit("second commit");
+
+        let previous = git.set_branch_to_commit(&feature, second, None).unwrap();
+        assert_eq!(previous, Some(first));
+        let stat = git.diff_stat(first, second).unwrap();
+        assert_eq!(
+            stat,
+            DiffStat {
+                files_changed: 2,
+                insertions: 2,
+                deletions: 1,
+            }
+        );
+        assert_eq!(
+            stat.to_string(),
+            "2 files changed, 2 insertions(+), 1 deletions(-)"
+        );
+    }
+
+    #[test]
+    fn test_list_commits_parents() {
+        let (main, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        repo.new_file("first", "first");
+        repo.add_all();
+        let first = repo.commit("first commit on test");
+        repo.create_branch("side");
+        repo.new_file("side", "side");
+        repo.add_all();
+        let side = repo.commit("commit on side");
+        repo.checkout("test");
+        repo.new_file("second", "second");
+        repo.add_all();
+        let second = repo.commit("second commit on test");
+        git!(repo, "merge", "--no-ff", "-m", "merge side", "side");
+
+        let git = Git::open(&repo.path()).unwrap();
+        let commits = git.list_commits::<String>().unwrap();
+        let parents = |oid: Oid| {
+            commits
+                .iter()
+                .find(|commit| commit.id == oid)
+                .map(|commit| commit.parents.clone())
+                .unwrap()
+        };
+        assert_eq!(parents(first), vec![main]);
+        assert_eq!(parents(second), vec![first]);
+        let merge = commits.last().unwrap();
+        assert_eq!(merge.title, "merge side");
+        assert_eq!(merge.parents, vec![second, side]);
+    }
+
+    #[test]
+    fn test_list_commits_preloads_notes() {
+        let (_, repo) = init_repo_with_commit();
+        repo.create_branch("test");
+        let mut oids = Vec::default();
+        for name in ["first", "second", "third"] {
+            repo.new_file(name, name);
+            repo.add_all();
+            oids.push(repo.commit(name));
+        }
+        let git = Git::open(&repo.path()).unwrap();
+        git.set_note(oids[0], "first note".to_string()).unwrap();
+        git.set_note(oids[2], "third note".to_string()).unwrap();
+
+        let git = Git::open(&repo.path()).unwrap();
+        let listed: Vec<Option<String>> = git
+            .list_commits::<String>()
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.note)
+            .collect();
+        // A new instance, so that nothing is cached
+        let git = Git::open(&repo.path()).unwrap();
+        let read: Vec<Option<String>> = oids
+            .iter()
+            .map(|oid| git.find_note::<String>(*oid).unwrap())
+            .collect();
+        assert_eq!(listed, read);
+        assert_eq!(
+            listed,
+            vec![
+                Some("first note".to_string()),
+                None,
+                Some("third note".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_branch_is_not_forced() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let feature = BranchName::from("feature");
+        for mode in [PushMode::Force, PushMode::ForceWithLease(head)] {
+            assert_eq!(
+                git.refspec_for(&feature, None, &mode, false),
+                "refs/heads/feature:refs/heads/feature"
+            );
+            assert_eq!(
+                git.refspec_for(&feature, None, &mode, true),
+                "+refs/heads/feature:refs/heads/feature"
+            );
+        }
+        assert_eq!(
+            git.refspec_for(&feature, None, &PushMode::Normal, true),
+            "refs/heads/feature:refs/heads/feature"
+        );
+    }
+
+    #[test]
+    fn test_push_ref_prefix() {
+        let (_, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let main = BranchName::from("main");
+        assert_eq!(
+            git.push_refspec(&main, None, true),
+            "+refs/heads/main:refs/heads/main"
+        );
+
+        git_config!(repo, "yggit.pushRefPrefix", "refs/for/");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(
+            git.push_refspec(&main, None, true),
+            "refs/heads/main:refs/for/main"
+        );
+        git.push_force_with_lease(&Origin::from("origin"), &main)
+            .expect("should be pushed");
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(remote.contains("refs/for/main"));
+        assert!(!remote.contains("refs/heads/main"));
+    }
+
+    #[test]
+    fn test_tracking_status() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        let first = repo.commit("first commit");
+        repo.push();
+        repo.new_file("second.md", "General Kenobi\n");
+        repo.add_all();
+        let second = repo.commit("second commit");
+
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        let status = |oid| git.tracking_status(&origin, &main, oid).unwrap();
+        assert_eq!(status(first), TrackingStatus::UpToDate);
+        assert_eq!(status(second), TrackingStatus::Ahead);
+
+        repo.push();
+        assert_eq!(status(first), TrackingStatus::Behind);
+        git!(repo, "reset", "--hard", &first.to_string());
+        repo.new_file("other.md", "Hello there\n");
+        repo.add_all();
+        let other = repo.commit("other commit");
+        assert_eq!(status(other), TrackingStatus::Diverged);
+
+        let feature = BranchName::from("feature");
+        assert_eq!(
+            git.tracking_status(&origin, &feature, other).unwrap(),
+            TrackingStatus::NotPushed
+        );
+    }
+
+    #[test]
+    fn test_https_token() {
+        let fallback =
+            |_: &str, _: Option<&str>, _: CredentialType| Err(Error::from_str("fallback"));
+        let mut credentials = with_token(fallback, Some("secret".to_string()));
+
+        let userpass = CredentialType::USER_PASS_PLAINTEXT;
+        let ssh = CredentialType::SSH_KEY;
+        assert!(credentials("git@github.com:Pilou97/yggit.git", None, ssh).is_err());
+        let cred = credentials("https://github.com/Pilou97/yggit.git", None, userpass).unwrap();
+        assert_eq!(cred.credtype(), userpass.bits());
+        // A refused token is not offered again
+        assert!(credentials("https://github.com/Pilou97/yggit.git", None, userpass).is_err());
+
+        let mut credentials = with_token(fallback, None);
+        assert!(credentials("https://github.com/Pilou97/yggit.git", None, userpass).is_err());
+    }
+
+    #[test]
+    fn test_https_credentials_error() {
+        let auth = || Error::new(ErrorCode::Auth, ErrorClass::Http, "authentication failed");
+        let url = "https://github.com/Pilou97/yggit.git";
+        let error = credentials_error(Some(url), auth(), &[]);
+        let error = error.downcast_ref::<NoHttpsCredentials>().unwrap();
+        assert_eq!(error.url, url);
+
+        let error = credentials_error(Some("git@github.com:Pilou97/yggit.git"), auth(), &[]);
+        assert!(error.downcast_ref::<NoHttpsCredentials>().is_none());
+        let error = credentials_error(Some(url), Error::from_str("network"), &[]);
+        assert!(error.downcast_ref::<NoHttpsCredentials>().is_none());
+    }
+
+    #[test]
+    fn test_ssh_authentication_methods() {
+        let home = tempfile::tempdir().unwrap();
+        let ssh_dir = home.path().join(".ssh");
+        std::fs::create_dir(&ssh_dir).unwrap();
+        for file in [
+            "id_work",
+            "id_work.pub",
+            "id_ed25519",
+            "id_ed25519.pub",
+            "id_rsa",
+            "known_hosts",
+            "config",
+        ] {
+            std::fs::write(ssh_dir.join(file), "key").unwrap();
+        }
+
+        let (_, tried) = authenticator(Some(&ssh_dir), None);
+        let key = |name: &str| ssh_dir.join(name).display().to_string();
+        assert_eq!(
+            tried,
+            vec![
+                "ssh-agent".to_string(),
+                key("id_rsa"),
+                key("id_ed25519"),
+                key("id_work")
+            ]
+        );
+
+        // The forced key replaces the agent and the other keys
+        let (_, tried) = authenticator(Some(&ssh_dir), Some(ssh_dir.join("id_work")));
+        assert_eq!(tried, vec![key("id_work")]);
+
+        let auth = Error::new(ErrorCode::Auth, ErrorClass::Ssh, "authentication failed");
+        let url = "git@github.com:Pilou97/yggit.git";
+        let error = credentials_error(Some(url), auth, &tried);
+        let error = error.downcast_ref::<SshAuthenticationFailed>().unwrap();
+        assert_eq!(error.url, url);
+        assert!(error
+            .to_string()
+            .contains(&format!("tried: {}", key("id_work"))));
+    }
+
+    #[test]
+    fn test_fetch() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        let clone = repo.clone();
+
+        repo.amend("hello there");
+        repo.push();
+        let remote = git!(repo, "rev-parse", "HEAD");
+
+        let git = Git::open(&clone.path()).expect("git should be open");
+        let origin = Origin::from("origin");
+        git.fetch(&origin).expect("origin should be fetched");
+        let tracking = git!(clone, "rev-parse", "refs/remotes/origin/main");
+        assert_eq!(tracking, remote);
+        // The local branch is not updated
+        assert_ne!(git!(clone, "rev-parse", "main"), remote);
+
+        let error = git.fetch(&Origin::from("unknown")).unwrap_err();
+        let error = error.downcast_ref::<FetchFailed>().unwrap();
+        assert_eq!(error.origin, Origin::from("unknown"));
+    }
+
+    #[test]
+    fn test_delete_remote_branch() {
+        let (_, repo) = init_repo_with_commit();
+        repo.push();
+        git!(repo, "push", "origin", "main:feature");
+
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+        let feature = BranchName::from("feature");
+        assert!(git
+            .delete_remote_branch(&origin, &feature)
+            .expect("the branch should be deleted"));
+        let remote = git!(repo, "ls-remote", "origin");
+        assert!(!remote.contains("refs/heads/feature"));
+        assert!(remote.contains("refs/heads/main"));
+
+        // The branch does not exist anymore
+        assert!(!git
+            .delete_remote_branch(&origin, &feature)
+            .expect("nothing should be done"));
+    }
+
+    #[test]
+    fn test_edit_in_temporary_file() {
+        struct Recorder(std::rc::Rc<std::cell::RefCell<String>>);
+        impl crate::editor::Editor for Recorder {
+            fn edit(&self, file_path: &str) -> anyhow::Result<String> {
+                *self.0.borrow_mut() = file_path.to_string();
+                Ok(std::fs::read_to_string(file_path)?)
+            }
+        }
+
+        let (_, repo) = init_repo_with_commit();
+        let mut git = Git::open(&repo.path()).unwrap();
+        let path = std::rc::Rc::default();
+        git.set_editor(Box::new(Recorder(std::rc::Rc::clone(&path))));
+
+        let content = "f8fa328 first commit\n-> feature\n";
+        assert_eq!(git.edit(content).unwrap(), content);
+        let path = path.borrow();
+        assert!(path.ends_with(".yggit"));
+        assert!(std::path::Path::new(path.as_str()).starts_with(std::env::temp_dir()));
+        assert!(!std::path::Path::new(path.as_str()).exists());
+    }
+
+    #[test]
+    fn test_remote_branch() {
+        let (head, repo) = init_repo_with_commit();
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        assert!(!git.remote_branch_exists(&origin, &main));
+        assert_eq!(git.remote_branch_oid(&origin, &main), None);
+
+        repo.push();
+        assert!(git.remote_branch_exists(&origin, &main));
+        assert_eq!(git.remote_branch_oid(&origin, &main), Some(head));
+        assert!(!git.remote_branch_exists(&Origin::from("fork"), &main));
+    }
+
+    #[test]
+    fn test_push_outcome() {
+        let (_, repo) = init_repo_with_commit();
+        repo.push();
+        let git = Git::open(&repo.path()).unwrap();
+        let origin = Origin::from("origin");
+        let main = BranchName::from("main");
+        let feature = BranchName::from("feature");
+        git!(repo, "branch", "feature");
+
+        assert_eq!(
+            git.push_force_with_lease(&origin, &feature).unwrap(),
+            PushOutcome::New
+        );
+        assert_eq!(
+            git.push_force_with_lease(&origin, &main).unwrap(),
+            PushOutcome::UpToDate
+        );
+        repo.amend("rewritten commit");
+        let git = Git::open(&repo.path()).unwrap();
+        assert_eq!(git.push_force(&origin, &main).unwrap(), PushOutcome::Update);
     }
 }
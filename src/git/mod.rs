@@ -2,6 +2,20 @@ mod config;
 
 #[allow(clippy::module_inception)]
 mod git;
+mod names;
+mod signing;
+#[cfg(test)]
+pub mod tmp;
 
+pub use config::Ui;
 pub use git::EnhancedCommit;
+pub use git::FetchFailed;
 pub use git::Git;
+pub use git::NoHttpsCredentials;
+pub use git::PushOutcome;
+pub use git::RefTypeConflict;
+pub use git::SshAuthenticationFailed;
+pub use git::TrackingStatus;
+pub use names::BranchName;
+pub use names::Origin;
+pub use signing::SigningFailed;
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// Name of a remote, for instance `origin`, or the url of an anonymous remote
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Origin(String);
+
+/// Name of a branch, for instance `feature/my-branch`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct BranchName(String);
+
+macro_rules! impl_name {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                Self(value.to_string())
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+impl Origin {
+    /// Returns true if the origin is a url instead of the name of a remote
+    pub fn is_url(&self) -> bool {
+        self.0.contains("://") || self.0.starts_with("git@")
+    }
+}
+
+impl_name!(Origin);
+impl_name!(BranchName);
+
+#[cfg(test)]
+mod tests {
+    use super::{BranchName, Origin};
+
+    #[test]
+    fn test_serde_transparent() {
+        let origin = Origin::from("origin");
+        let json = serde_json::to_string(&origin).expect("should be serialized");
+        assert_eq!(json, "\"origin\"");
+
+        let branch: BranchName = serde_json::from_str("\"feature\"").expect("should be parsed");
+        assert_eq!(branch.as_str(), "feature");
+    }
+
+    #[test]
+    fn test_origin_is_url() {
+        assert!(!Origin::from("origin").is_url());
+        assert!(Origin::from("file:///tmp/repo.git").is_url());
+        assert!(Origin::from("git@github.com:Pilou97/yggit.git").is_url());
+    }
+}
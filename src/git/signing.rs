@@ -0,0 +1,88 @@
+use super::config::{Signing, SigningFormat};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// The notes commit could not be signed with the configured program
+#[derive(Debug)]
+pub struct SigningFailed {
+    pub reason: String,
+}
+
+impl std::fmt::Display for SigningFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot sign the notes: {}", self.reason)
+    }
+}
+
+impl std::error::Error for SigningFailed {}
+
+fn failed(reason: impl ToString) -> SigningFailed {
+    SigningFailed {
+        reason: reason.to_string(),
+    }
+}
+
+/// Sign the content like git signs a commit, returns the armored signature
+pub fn sign(signing: &Signing, content: &str) -> Result<String, SigningFailed> {
+    let mut command = Command::new(&signing.program);
+    // A literal ssh key is handed to ssh-keygen through a file, kept until the signature is done
+    let mut _literal_key = None;
+    match signing.format {
+        SigningFormat::OpenPgp | SigningFormat::X509 => {
+            command.args(["--status-fd=2", "-bsa"]);
+            if let Some(key) = &signing.key {
+                command.args(["-u", key]);
+            }
+        }
+        SigningFormat::Ssh => {
+            let key = signing
+                .key
+                .as_deref()
+                .ok_or_else(|| failed("user.signingKey is not set"))?;
+            let key_path = if let Some(literal) = key.strip_prefix("key::") {
+                let mut file = tempfile::NamedTempFile::new().map_err(failed)?;
+                file.write_all(literal.as_bytes()).map_err(failed)?;
+                let path = file.path().to_path_buf();
+                _literal_key = Some(file);
+                path
+            } else if let Some(path) = key.strip_prefix("~/") {
+                let home = std::env::var("HOME").map_err(|_| failed("HOME is not set"))?;
+                std::path::Path::new(&home).join(path)
+            } else {
+                key.into()
+            };
+            command
+                .args(["-Y", "sign", "-n", "git", "-f"])
+                .arg(key_path);
+        }
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| failed(format!("cannot run {}: {}", signing.program, error)))?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| failed("cannot write to the signing program"))?
+        .write_all(content.as_bytes())
+        .map_err(failed)?;
+    let output = child.wait_with_output().map_err(failed)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(failed(format!(
+            "{} failed: {}",
+            signing.program,
+            stderr.trim()
+        )));
+    }
+    let signature = String::from_utf8(output.stdout).map_err(failed)?;
+    if signature.trim().is_empty() {
+        return Err(failed(format!("{} gave no signature", signing.program)));
+    }
+    Ok(signature)
+}
@@ -0,0 +1,324 @@
+use crate::{
+    commands::push::Push,
+    editor::Editor,
+    git::{
+        config::{Core, GitConfig, Ui, User, Yggit},
+        Git,
+    },
+};
+use anyhow::{Context, Result};
+use clap::{Args, Command, FromArgMatches};
+use git2::Oid;
+use serde::Serialize;
+use std::io::Write;
+use tempfile::TempDir;
+
+macro_rules! execute_commands {
+    ($($cmd:expr $(, $arg:expr)*)* ) => {
+        {
+            $(
+                let cmd_string = format!("{} {}", $cmd, vec![$($arg),*].join(" "));
+                println!("{}", cmd_string);
+                let child = std::process::Command::new($cmd)
+                    $(.arg($arg))*
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .expect("Failed to spawn child process");
+
+                let output = child.wait_with_output().expect("Failed to read stdout");
+                if !output.status.success() {
+                    panic!("the command did not succeed");
+                }
+                String::from_utf8(output.stdout).expect("should be parasable")
+            )*
+        }
+    };
+}
+
+pub(crate) use execute_commands;
+
+macro_rules! git {
+    ($self:ident, $($args:expr),* ) => {
+        $crate::git::tmp::execute_commands!("git", "-C", &$self.path(), $($args),*)
+    };
+}
+
+pub(crate) use git;
+
+macro_rules! git_config {
+    ($self:ident, $($args:expr),* ) => {
+        $crate::git::tmp::git!($self, "config", "--local", $($args),*)
+    };
+}
+
+pub(crate) use git_config;
+
+pub struct GitTmp {
+    pub bare: Option<TempDir>,
+    pub directory: TempDir,
+}
+
+impl Clone for GitTmp {
+    fn clone(&self) -> Self {
+        let clone = TempDir::new().expect("directory should be created");
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository: impossible to clone")
+        };
+
+        execute_commands!(
+            "git",
+            "clone",
+            &format!("file://{}", bare.path().to_str().unwrap()),
+            clone.path().to_str().unwrap()
+        );
+
+        let git = Self {
+            bare: None,
+            directory: clone,
+        };
+
+        git.init_config();
+
+        git
+    }
+}
+
+/// Editor changing the content of the todo with a function, instead of the user
+struct ScriptedEditor<F>(F);
+
+impl<F> Editor for ScriptedEditor<F>
+where
+    F: Fn(String) -> String,
+{
+    fn edit(&self, file_path: &str) -> Result<String> {
+        let content = std::fs::read_to_string(file_path).context("Cannot read the todo")?;
+        Ok((self.0)(content))
+    }
+}
+
+/// Helper that execute git command
+///
+/// So that yggit can be tested against the git binary
+impl GitTmp {
+    /// Create a repository with a bare one
+    pub fn init_bare(initial_branch: &str) -> Self {
+        let bare = tempfile::Builder::new()
+            .suffix(".git")
+            .tempdir()
+            .expect("git bare folder to be created");
+
+        execute_commands!(
+            "git",
+            "-C",
+            bare.path().to_str().unwrap(),
+            "init",
+            "--initial-branch",
+            initial_branch,
+            "--bare"
+        );
+
+        // Then we clone it
+        let clone = TempDir::new().expect("Directory should be created");
+
+        execute_commands!(
+            "git",
+            "clone",
+            &format!("file://{}", bare.path().to_str().unwrap()),
+            clone.path().to_str().unwrap()
+        );
+
+        let git = Self {
+            bare: Some(bare),
+            directory: clone,
+        };
+
+        git.init_config();
+
+        git
+    }
+
+    /// This function has to be called in each constructor
+    /// Later we can add an optional argument Config
+    pub fn init_config(&self) {
+        // TODO: put this in config.rs as dummy in test module
+        let config = GitConfig {
+            user: User {
+                email: "example@example.com".to_string(),
+                name: "Obi-wan".to_string(),
+            },
+            core: Core {
+                editor: Some("theforce".to_string()), // The editor is not tested
+                pager: None,
+            },
+            yggit: Yggit {
+                default_upstream: "origin".to_string(),
+                notes_ref: "refs/notes/commits".to_string(),
+                max_stack_size: 100,
+                ui: Ui::Terminal,
+                push_ref_prefix: "refs/heads/".to_string(),
+                default_branch: None,
+            },
+            signing: None,
+        };
+
+        git_config!(self, "user.email", config.user.email.as_str());
+        git_config!(self, "user.name", config.user.name.as_str());
+        git_config!(self, "core.editor", config.core.editor.as_deref().unwrap());
+        git_config!(
+            self,
+            "yggit.defaultUpstream",
+            config.yggit.default_upstream.as_str()
+        );
+        git_config!(self, "notes.rewriteRef", config.yggit.notes_ref.as_str());
+    }
+
+    /// Add a file to the repository
+    pub fn new_file(&self, file_name: &str, content: &str) {
+        let path = self.directory.path().join(file_name);
+        let mut file = std::fs::File::create(path).expect("file should be created");
+        file.write_all(content.as_bytes())
+            .expect("should have written file to disk");
+    }
+
+    /// Add all files to the next commit
+    pub fn add_all(&self) {
+        let _ = git!(self, "add", ".");
+    }
+
+    /// Commit the change
+    pub fn commit(&self, commit_name: &str) -> Oid {
+        let _ = git!(self, "commit", "-m", commit_name);
+        let oid = git!(self, "rev-parse", "HEAD");
+        let oid = oid.trim();
+
+        Oid::from_str(oid).unwrap()
+    }
+
+    pub fn add_note<N>(&self, oid: Oid, note: &N)
+    where
+        N: Serialize,
+    {
+        let json = serde_json::to_string(note).expect("note");
+        git!(self, "notes", "add", "-m", &json, &oid.to_string());
+    }
+
+    /// Returns the raw message of the note
+    pub fn show_note(&self, oid: Oid) -> String {
+        git!(self, "notes", "show", &oid.to_string())
+    }
+
+    pub fn push(&self) {
+        git!(self, "push", "--force");
+    }
+
+    /// Run `yggit push` in the repository, the todo is edited by the given function
+    pub fn run_push<F>(&self, editor: F, force: bool, onto: Option<&str>) -> Result<()>
+    where
+        F: Fn(String) -> String + 'static,
+    {
+        let mut git = Git::open(&self.path())?;
+        git.set_onto(onto.map(ToString::to_string));
+        git.set_editor(Box::new(ScriptedEditor(editor)));
+
+        let mut args = vec!["push"];
+        if force {
+            args.push("--force");
+        }
+        let matches = Push::augment_args(Command::new("push")).try_get_matches_from(args)?;
+        Push::from_arg_matches(&matches)?.execute(git)
+    }
+
+    /// Lock a reference of the bare repository, so that it cannot be updated
+    pub fn lock_bare_ref(&self, refname: &str) {
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository")
+        };
+        let path = bare.path().join(format!("{}.lock", refname));
+        std::fs::File::create(path).expect("lock should be created");
+    }
+
+    /// Set a configuration of the bare repository, like the settings of a server
+    pub fn bare_config(&self, key: &str, value: &str) {
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository")
+        };
+        execute_commands!(
+            "git",
+            "-C",
+            bare.path().to_str().unwrap(),
+            "config",
+            key,
+            value
+        );
+    }
+
+    /// Replace the pack directory of the bare repository with a file,
+    /// so that a push fails once it starts sending objects, after the negotiation
+    pub fn break_bare_packs(&self) {
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository")
+        };
+        let packs = bare.path().join("objects").join("pack");
+        std::fs::remove_dir_all(&packs).expect("pack directory should be removed");
+        std::fs::File::create(packs).expect("file should be created");
+    }
+
+    /// Restore the pack directory removed by `break_bare_packs`
+    pub fn repair_bare_packs(&self) {
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository")
+        };
+        let packs = bare.path().join("objects").join("pack");
+        std::fs::remove_file(&packs).expect("file should be removed");
+        std::fs::create_dir(packs).expect("pack directory should be created");
+    }
+
+    /// Returns the path of the repository
+    pub fn path(&self) -> String {
+        self.directory.path().to_str().unwrap().to_string()
+    }
+
+    /// Modifies the title of HEAD
+    pub fn amend(&self, title: &str) {
+        git!(self, "commit", "--amend", "-m", title);
+    }
+
+    /// pull the repository
+    pub fn pull(&self) {
+        git!(self, "pull");
+    }
+
+    pub fn create_branch(&self, branch_name: &str) {
+        git!(self, "checkout", "-b", branch_name);
+    }
+
+    pub fn checkout(&self, branch_name: &str) {
+        git!(self, "checkout", branch_name);
+    }
+
+    /// Clone the bare repository with a truncated history
+    pub fn shallow_clone(&self, depth: usize) -> Self {
+        let clone = TempDir::new().expect("directory should be created");
+        let Some(ref bare) = self.bare else {
+            todo!("no bare repository: impossible to clone")
+        };
+
+        execute_commands!(
+            "git",
+            "clone",
+            "--depth",
+            &depth.to_string(),
+            &format!("file://{}", bare.path().to_str().unwrap()),
+            clone.path().to_str().unwrap()
+        );
+
+        let git = Self {
+            bare: None,
+            directory: clone,
+        };
+
+        git.init_config();
+
+        git
+    }
+}
@@ -1,13 +1,30 @@
+use crate::core::{DuplicateBranch, InvalidBranchName, PushFailed};
+use anyhow::Result;
 use clap::Parser;
 use clap::Subcommand;
+use color::ColorChoice;
 use commands::apply::Apply;
+use commands::clean::Clean;
+use commands::init::Init;
+use commands::migrate_notes::MigrateNotes;
 use commands::push::Push;
+use commands::rebase::Rebase;
+use commands::reset::Reset;
 use commands::show::Show;
+use commands::status::Status;
+use commands::test::Test;
 use git::Git;
+use git::NoHttpsCredentials;
+use git::SshAuthenticationFailed;
+use git::Ui;
+use parser::{OrphanBranchLine, SyntaxError};
 
+mod color;
 mod commands;
 mod core;
+mod editor;
 mod git;
+mod pager;
 mod parser;
 
 #[derive(Debug, Parser)] // requires `derive` feature
@@ -16,6 +33,38 @@ mod parser;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// when to colorize the output
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// never colorize the output, same as --color never
+    #[arg(long, global = true, default_value_t = false)]
+    no_color: bool,
+    /// skip the check that HEAD descends from the main branch,
+    /// if it does not, the whole history is listed
+    #[arg(long, global = true, default_value_t = false)]
+    assume_unchanged_base: bool,
+    /// do not pipe the output into a pager
+    #[arg(long, global = true, default_value_t = false)]
+    no_pager: bool,
+    /// branch, remote-tracking branch, tag or commit the stack is based on, remembered for the current branch,
+    /// main/master by default
+    #[arg(long, global = true)]
+    onto: Option<String>,
+    /// list the whole history, back to the root commit, instead of stopping at the base
+    #[arg(long, global = true, default_value_t = false, conflicts_with = "onto")]
+    root: bool,
+    /// base the stack on the most recent tag matching the glob, like `v*`
+    #[arg(long, global = true, conflicts_with_all = ["onto", "root"])]
+    since_tag: Option<String>,
+    /// name used to write the notes, instead of user.name
+    #[arg(long, global = true)]
+    author_name: Option<String>,
+    /// email used to write the notes, instead of user.email
+    #[arg(long, global = true)]
+    author_email: Option<String>,
+    /// read the edited todo from stdin instead of opening the editor, same as yggit.ui = stdin
+    #[arg(long, global = true, default_value_t = false)]
+    from_stdin: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -23,17 +72,130 @@ enum Commands {
     Push(Push),
     Show(Show),
     Apply(Apply),
+    Rebase(Rebase),
+    Init(Init),
+    Reset(Reset),
+    Status(Status),
+    Clean(Clean),
+    Test(Test),
+    MigrateNotes(MigrateNotes),
 }
 
 fn main() {
     let args = Cli::parse();
 
-    let git = Git::open(".").unwrap();
+    color::init(if args.no_color {
+        ColorChoice::Never
+    } else {
+        args.color
+    });
+
+    if args.no_pager {
+        pager::disable();
+    }
+
+    // A failure is a message for the user, not a panic with a stack trace
+    if let Err(error) = run(args) {
+        eprintln!("error: {error:#}");
+        std::process::exit(exit_code(&error));
+    }
+}
+
+/// Exit code of any other error
+const EXIT_FAILURE: i32 = 1;
+/// Exit code when the edited todo is not valid
+const EXIT_INVALID_TODO: i32 = 2;
+/// Exit code when the remote refused the authentication or some branches have not been pushed
+const EXIT_PUSH_FAILED: i32 = 3;
+
+/// Returns the exit code of the error, from the most specific of its causes
+fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if cause.is::<SyntaxError>()
+            || cause.is::<OrphanBranchLine>()
+            || cause.is::<InvalidBranchName>()
+            || cause.is::<DuplicateBranch>()
+        {
+            return EXIT_INVALID_TODO;
+        }
+        if cause.is::<PushFailed>()
+            || cause.is::<NoHttpsCredentials>()
+            || cause.is::<SshAuthenticationFailed>()
+        {
+            return EXIT_PUSH_FAILED;
+        }
+    }
+    EXIT_FAILURE
+}
+
+fn run(args: Cli) -> Result<()> {
+    // The config required to open the repository is written by init
+    if let Commands::Init(init) = &args.command {
+        return init.execute();
+    }
+
+    let mut git = Git::open(".")?;
+    git.assume_unchanged_base(args.assume_unchanged_base);
+    let remember_onto = args.onto.is_some();
+    git.set_onto(args.onto.or_else(|| git.remembered_onto()));
+    git.include_root(args.root);
+    git.set_since_tag(args.since_tag);
+    // The onto branch is remembered per branch once it is known to be a valid base,
+    // the read-only commands never write it
+    if remember_onto && !matches!(args.command, Commands::Show(_) | Commands::Status(_)) {
+        git.remember_onto()?;
+    }
+    git.override_identity(args.author_name.as_deref(), args.author_email.as_deref())?;
+    if args.from_stdin {
+        git.set_editor(editor::from_config(Ui::Stdin, None));
+    }
 
     match args.command {
         Commands::Push(push) => push.execute(git),
         Commands::Show(show) => show.execute(git),
         Commands::Apply(apply) => apply.execute(git),
+        Commands::Rebase(rebase) => rebase.execute(git),
+        Commands::Reset(reset) => reset.execute(git),
+        Commands::Status(status) => status.execute(git),
+        Commands::Clean(clean) => clean.execute(git),
+        Commands::Test(test) => test.execute(git),
+        Commands::MigrateNotes(migrate) => migrate.execute(git),
+        Commands::Init(_) => unreachable!("init is executed before opening the repository"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exit_code, EXIT_FAILURE, EXIT_INVALID_TODO, EXIT_PUSH_FAILED};
+    use crate::git::tmp::{git, GitTmp};
+
+    #[test]
+    fn test_exit_code() {
+        let repo = GitTmp::init_bare("main");
+        repo.new_file("readme.md", "Hello there\n");
+        repo.add_all();
+        repo.commit("first commit");
+        repo.push();
+        repo.create_branch("stack");
+        repo.new_file("feature.md", "General Kenobi\n");
+        repo.add_all();
+        let head = repo.commit("feature commit");
+        let missing = tempfile::tempdir().unwrap();
+        let url = missing.path().join("missing.git");
+        git!(repo, "remote", "set-url", "origin", url.to_str().unwrap());
+
+        let add_branch = move |todo: String| {
+            let line = format!("{} feature commit\n", head);
+            todo.replace(&line, &format!("{line}-> feature\n"))
+        };
+        let error = repo
+            .run_push(|todo| format!("not a commit\n{todo}"), false, None)
+            .unwrap_err();
+        assert_eq!(exit_code(&error), EXIT_INVALID_TODO);
+
+        let error = repo.run_push(add_branch, false, None).unwrap_err();
+        assert_eq!(exit_code(&error), EXIT_PUSH_FAILED);
+
+        assert_eq!(exit_code(&anyhow::Error::msg("failure")), EXIT_FAILURE);
     }
-    .unwrap()
 }
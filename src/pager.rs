@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use std::{
+    io::{IsTerminal, Write},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Never use a pager, the output goes straight to stdout
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns the pager to use, None if the output has to be printed directly
+///
+/// Like git, GIT_PAGER has the priority over core.pager, then PAGER, then less
+fn pager_command(
+    disabled: bool,
+    is_terminal: bool,
+    git_pager: Option<String>,
+    core_pager: Option<&str>,
+    pager: Option<String>,
+) -> Option<String> {
+    if disabled || !is_terminal {
+        return None;
+    }
+    let command = git_pager
+        .or(core_pager.map(ToString::to_string))
+        .or(pager)
+        .unwrap_or("less".to_string());
+    let command = command.trim();
+    if command.is_empty() || command == "cat" {
+        return None;
+    }
+    Some(command.to_string())
+}
+
+/// Print the output through the pager when stdout is a terminal
+pub fn page(output: &str, core_pager: Option<&str>) -> Result<()> {
+    let command = pager_command(
+        DISABLED.load(Ordering::Relaxed),
+        std::io::stdout().is_terminal(),
+        std::env::var("GIT_PAGER").ok(),
+        core_pager,
+        std::env::var("PAGER").ok(),
+    );
+    let Some(command) = command else {
+        print!("{}", output);
+        return Ok(());
+    };
+
+    let mut pager = Command::new("sh");
+    pager.arg("-c").arg(&command).stdin(Stdio::piped());
+    // Same defaults as git: quit if one screen, keep the colors, do not clear the screen
+    if std::env::var_os("LESS").is_none() {
+        pager.env("LESS", "FRX");
+    }
+    if std::env::var_os("LV").is_none() {
+        pager.env("LV", "-c");
+    }
+    let mut child = pager.spawn().context("Cannot start the pager")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user can quit the pager before reading everything
+        let _ = stdin.write_all(output.as_bytes());
+    }
+    child.wait().context("Cannot wait for the pager")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pager_command;
+
+    #[test]
+    fn test_no_pager() {
+        let command = pager_command(true, true, None, Some("less"), None);
+        assert_eq!(command, None);
+    }
+
+    #[test]
+    fn test_no_pager_when_piped() {
+        let command = pager_command(false, false, None, Some("less"), None);
+        assert_eq!(command, None);
+    }
+
+    #[test]
+    fn test_pager_priority() {
+        let command = pager_command(
+            false,
+            true,
+            Some("most".to_string()),
+            Some("less -R"),
+            Some("more".to_string()),
+        );
+        assert_eq!(command.as_deref(), Some("most"));
+
+        let command = pager_command(false, true, None, Some("less -R"), Some("more".to_string()));
+        assert_eq!(command.as_deref(), Some("less -R"));
+
+        let command = pager_command(false, true, None, None, Some("more".to_string()));
+        assert_eq!(command.as_deref(), Some("more"));
+
+        let command = pager_command(false, true, None, None, None);
+        assert_eq!(command.as_deref(), Some("less"));
+
+        let command = pager_command(false, true, None, Some("cat"), None);
+        assert_eq!(command, None);
+    }
+}
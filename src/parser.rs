@@ -1,36 +1,316 @@
 // Git related
 
 use crate::{
-    core::{Note, Push},
-    git::EnhancedCommit,
+    color::{self, Color},
+    core::{Note, Push, PushStrategy, Reword, Tag},
+    git::{BranchName, EnhancedCommit, Origin},
 };
+use anyhow::Context;
 use git2::Oid;
 use pest::{iterators::Pair, Parser};
 use pest_derive::Parser;
+use serde::Serialize;
 
+/// What is rendered from a list of commits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Commits with their branches
+    #[default]
+    All,
+    /// Only the commits, without their branches
+    CommitsOnly,
+    /// Only the branches, one per line
+    BranchesOnly,
+}
+
+/// Write the commits and their branches as the todo edited by the user
+///
+/// The command testing a commit is written under it as `$ <command>`, so that it can be edited
 pub fn commits_to_string(commits: Vec<EnhancedCommit<Note>>) -> String {
+    render_commits(commits, Filter::All, Some("$ "), false, |_, text| {
+        text.to_string()
+    })
+}
+
+/// Same as `commits_to_string`, colorized for the terminal
+/// and restricted by the given filter
+///
+/// With tests, the command testing a commit is shown under it as `# $ <command>`
+pub fn commits_to_colored_string(
+    commits: Vec<EnhancedCommit<Note>>,
+    filter: Filter,
+    tests: bool,
+    descriptions: bool,
+) -> String {
+    let tests = tests.then_some("# $ ");
+    render_commits(commits, filter, tests, descriptions, color::paint)
+}
+
+/// Render the stack like `git log --graph --oneline`
+///
+/// The tip of the stack is at the top, the branches and tags are labels of their commit
+pub fn commits_to_graph(commits: Vec<EnhancedCommit<Note>>) -> String {
+    render_graph(commits, color::paint)
+}
+
+/// Render each commit on one line following the template, like `git log --format`
+///
+/// The placeholders are:
+///  - %h : short hash
+///  - %H : full hash
+///  - %s : title
+///  - %b : branches, separated by commas
+///  - %o : origin of the first branch
+///
+/// Unknown placeholders are kept as is
+pub fn commits_to_formatted_string(commits: Vec<EnhancedCommit<Note>>, format: &str) -> String {
     let mut output = String::default();
     for commit in commits {
-        output = format!("{}{} {}\n", output, commit.id, commit.title);
-        if let Some(Note { push }) = commit.note {
-            if let Some(Push {
-                origin: Some(origin),
-                branch,
-            }) = &push
-            {
-                output = format!("{}-> {}:{}\n", output, origin, branch);
-            } else if let Some(Push {
-                origin: None,
-                branch,
-            }) = &push
-            {
-                output = format!("{}-> {}\n", output, branch);
+        let id = commit.id.to_string();
+        let pushes = commit.note.map(|note| note.pushes).unwrap_or_default();
+        let mut line = String::default();
+        let mut chars = format.chars();
+        while let Some(char) = chars.next() {
+            if char != '%' {
+                line.push(char);
+                continue;
+            }
+            match chars.next() {
+                Some('h') => line.push_str(&id[..7]),
+                Some('H') => line.push_str(&id),
+                Some('s') => line.push_str(&single_line(&commit.title)),
+                Some('b') => {
+                    let branches: Vec<&str> =
+                        pushes.iter().map(|push| push.branch.as_str()).collect();
+                    line.push_str(&branches.join(","));
+                }
+                Some('o') => {
+                    if let Some(Push {
+                        origin: Some(origin),
+                        ..
+                    }) = pushes.first()
+                    {
+                        line.push_str(origin.as_str());
+                    }
+                }
+                Some(other) => {
+                    line.push('%');
+                    line.push(other);
+                }
+                None => line.push('%'),
+            }
+        }
+        output = format!("{}{}\n", output, line);
+    }
+    output
+}
+
+/// Render the titles changed in the todo as a diff, old title first
+pub fn rewords_to_string(rewords: &[Reword]) -> String {
+    let mut output = String::default();
+    for Reword { id, old, new } in rewords {
+        let id = id.to_string();
+        output = format!(
+            "{}{}\n{}\n{}\n",
+            output,
+            color::paint(Color::Yellow, &id[..7]),
+            color::paint(Color::Red, &format!("- {}", old)),
+            color::paint(Color::Green, &format!("+ {}", new)),
+        );
+    }
+    output
+}
+
+/// The stack, as rendered by `show --json`
+///
+/// The schema is stable, tools can rely on it
+#[derive(Debug, Serialize, PartialEq)]
+pub struct StackJson {
+    /// Base of the stack, null when the whole history is listed
+    pub onto: Option<String>,
+    /// Commits of the stack, from the oldest to the newest
+    pub entries: Vec<StackEntryJson>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct StackEntryJson {
+    pub oid: String,
+    pub title: String,
+    /// First branch pushed from the commit, null if there is none
+    pub branch: Option<BranchName>,
+    pub origin: Option<Origin>,
+    /// All the branches pushed from the commit, in order
+    pub branches: Vec<BranchName>,
+    /// Branch the commit is stacked on: the closest branch below it, or onto
+    pub base: Option<String>,
+}
+
+/// Build the stack rendered by `show --json`
+pub fn commits_to_json(commits: Vec<EnhancedCommit<Note>>, onto: Option<String>) -> StackJson {
+    let mut base = onto.clone();
+    let entries = commits
+        .into_iter()
+        .map(|commit| {
+            let pushes = commit.note.map(|note| note.pushes).unwrap_or_default();
+            let entry = StackEntryJson {
+                oid: commit.id.to_string(),
+                title: commit.title,
+                branch: pushes.first().map(|push| push.branch.clone()),
+                origin: pushes.first().and_then(|push| push.origin.clone()),
+                branches: pushes.iter().map(|push| push.branch.clone()).collect(),
+                base: base.clone(),
+            };
+            if let Some(push) = pushes.last() {
+                base = Some(push.branch.to_string());
+            }
+            entry
+        })
+        .collect();
+    StackJson { onto, entries }
+}
+
+/// Keep the title on a single line, so that the todo file stays parsable
+pub fn single_line(title: &str) -> String {
+    title.replace(['\n', '\r'], " ")
+}
+
+fn render_graph<F>(commits: Vec<EnhancedCommit<Note>>, paint: F) -> String
+where
+    F: Fn(Color, &str) -> String,
+{
+    let mut output = String::default();
+    for commit in commits.into_iter().rev() {
+        let mut labels = Vec::default();
+        if let Some(Note { pushes, tag, .. }) = commit.note {
+            for push in pushes {
+                match push {
+                    Push {
+                        origin: Some(origin),
+                        branch,
+                        ..
+                    } => labels.push(format!("{}:{}", origin, branch)),
+                    Push {
+                        origin: None,
+                        branch,
+                        ..
+                    } => labels.push(branch.to_string()),
+                }
+            }
+            if let Some(Tag { name, .. }) = tag {
+                labels.push(format!("tag: {}", name));
+            }
+        }
+        let labels = if labels.is_empty() {
+            String::default()
+        } else {
+            format!("({}) ", paint(Color::Green, &labels.join(", ")))
+        };
+        let id = commit.id.to_string();
+        output = format!(
+            "{}* {} {}{}\n",
+            output,
+            paint(Color::Yellow, &id[..7]),
+            labels,
+            single_line(&commit.title)
+        );
+    }
+    output
+}
+
+fn render_commits<F>(
+    commits: Vec<EnhancedCommit<Note>>,
+    filter: Filter,
+    // Prefix of the line of the test command, hidden when None
+    tests: Option<&str>,
+    descriptions: bool,
+    paint: F,
+) -> String
+where
+    F: Fn(Color, &str) -> String,
+{
+    let mut output = String::default();
+    for commit in commits {
+        if filter == Filter::All {
+            for comment in commit.note.iter().flat_map(|note| &note.comments) {
+                if comment.is_empty() {
+                    output = format!("{}#\n", output);
+                } else {
+                    output = format!("{}# {}\n", output, comment);
+                }
+            }
+        }
+        if filter != Filter::BranchesOnly {
+            let id = paint(Color::Yellow, &commit.id.to_string());
+            // No trailing space after the id when the title is empty
+            if commit.title.is_empty() {
+                output = format!("{}{}\n", output, id);
+            } else {
+                output = format!("{}{} {}\n", output, id, single_line(&commit.title));
+            }
+            // The description is indented under the title, like `git log`
+            if let Some(description) = commit.description.as_ref().filter(|_| descriptions) {
+                for line in description.lines() {
+                    if line.is_empty() {
+                        output = format!("{}\n", output);
+                    } else {
+                        output = format!("{}    {}\n", output, line);
+                    }
+                }
+            }
+        }
+        if filter == Filter::CommitsOnly {
+            continue;
+        }
+        let Some(note) = commit.note else {
+            continue;
+        };
+        let test_command = note
+            .test_command()
+            .zip(tests)
+            .filter(|_| filter == Filter::All)
+            .map(|(command, prefix)| format!("{prefix}{command}"));
+        let Note { pushes, tag, .. } = note;
+        let has_target = !pushes.is_empty() || tag.is_some() || test_command.is_some();
+        for push in pushes {
+            let mut target = match &push {
+                Push {
+                    origin: Some(origin),
+                    branch,
+                    ..
+                } => format!("{}:{}", origin, branch),
+                Push {
+                    origin: None,
+                    branch,
+                    ..
+                } => branch.to_string(),
+            };
+            // The branches only output lists names, without the strategy
+            if let Some(strategy) = push.strategy.filter(|_| filter != Filter::BranchesOnly) {
+                target = format!("{} ({})", target, strategy.as_str());
             }
-            // An empty line is added so that is cleaner to differentiate the different MR
-            if push.is_some() {
-                output = format!("{}\n", output);
+            if filter == Filter::BranchesOnly {
+                output = format!("{}{}\n", output, paint(Color::Green, &target));
+            } else {
+                output = format!("{}-> {}\n", output, paint(Color::Green, &target));
             }
         }
+        if filter == Filter::BranchesOnly {
+            continue;
+        }
+        if let Some(Tag { name, message }) = tag {
+            let tag = match message {
+                Some(message) => format!("tag:{} \"{}\"", name, message),
+                None => format!("tag:{}", name),
+            };
+            output = format!("{}-> {}\n", output, paint(Color::Green, &tag));
+        }
+        if let Some(command) = test_command {
+            output = format!("{}{}\n", output, command);
+        }
+        // An empty line is added so that is cleaner to differentiate the different MR
+        if has_target {
+            output = format!("{}\n", output);
+        }
     }
     output
 }
@@ -41,16 +321,30 @@ struct YggitParser;
 
 #[derive(Debug, Clone)]
 pub struct Target {
-    pub origin: Option<String>,
-    pub branch: String,
+    pub origin: Option<Origin>,
+    pub branch: BranchName,
+    pub strategy: Option<PushStrategy>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagTarget {
+    pub name: String,
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Commit {
     pub hash: Oid,
-    #[allow(dead_code)]
     pub title: String,
-    pub target: Option<Target>,
+    /// Branches, in the order of the todo
+    pub targets: Vec<Target>,
+    pub tag: Option<TagTarget>,
+    /// Comments written above the commit line or between its branches
+    pub comments: Vec<String>,
+    /// Branches to delete from their remote, written `-> !<branch>`
+    pub deletions: Vec<Target>,
+    /// Command testing the commit, written `$ <command>` or `exec <command>`
+    pub test: Option<String>,
 }
 
 fn parse_target(pair: Pair<Rule>) -> Option<Target> {
@@ -58,14 +352,22 @@ fn parse_target(pair: Pair<Rule>) -> Option<Target> {
 
     let mut parsed_origin = None;
     let mut parsed_branch = None;
+    let mut parsed_strategy = None;
 
     for pair in target.into_iter() {
         match pair.as_rule() {
-            Rule::origin => {
-                parsed_origin = Some(pair.as_str().to_string());
+            Rule::origin | Rule::url => {
+                parsed_origin = Some(Origin::from(pair.as_str()));
             }
             Rule::branch_name => {
-                parsed_branch = Some(pair.as_str().to_string());
+                parsed_branch = Some(BranchName::from(pair.as_str()));
+            }
+            Rule::strategy => {
+                parsed_strategy = match pair.as_str() {
+                    "force" => Some(PushStrategy::Force),
+                    "lease" => Some(PushStrategy::Lease),
+                    _ => Some(PushStrategy::Normal),
+                };
             }
             _ => (),
         }
@@ -75,11 +377,45 @@ fn parse_target(pair: Pair<Rule>) -> Option<Target> {
     Some(Target {
         origin: parsed_origin,
         branch: parsed_branch,
+        strategy: parsed_strategy,
+    })
+}
+
+fn parse_tag(pair: Pair<Rule>) -> Option<TagTarget> {
+    let mut name = None;
+    let mut message = None;
+
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::tag_name => {
+                name = Some(pair.as_str().to_string());
+            }
+            Rule::tag_message => {
+                message = Some(pair.as_str().to_string());
+            }
+            _ => (),
+        }
+    }
+
+    Some(TagTarget {
+        name: name?,
+        message,
     })
 }
 
+fn parse_comment(pair: Pair<Rule>) -> String {
+    // The space after the # is added back when rendered
+    let comment = pair.as_str();
+    comment.strip_prefix(' ').unwrap_or(comment).to_string()
+}
+
 fn parse_commit(pair: Pair<Rule>) -> Option<Commit> {
-    let mut commit = pair.into_inner();
+    let mut commit = pair.into_inner().peekable();
+
+    let mut comments = Vec::default();
+    while let Some(comment) = commit.next_if(|pair| pair.as_rule() == Rule::comment) {
+        comments.push(parse_comment(comment));
+    }
 
     let git_commit = commit.next()?;
     let mut git_commit = git_commit.into_inner();
@@ -87,22 +423,44 @@ fn parse_commit(pair: Pair<Rule>) -> Option<Commit> {
     let hash = git_commit.next()?;
     let hash = Oid::from_str(hash.as_str()).ok()?;
 
-    let title = git_commit.next()?;
-    let title = title.as_str();
+    // The title is missing when the commit has an empty message
+    let title = git_commit
+        .next()
+        .map(|title| title.as_str())
+        .unwrap_or_default();
 
-    let mut target = None;
+    let mut targets = Vec::default();
+    let mut deletions = Vec::default();
+    let mut tag = None;
+    let mut test = None;
 
-    // Optional target
+    // Optional targets, tag and test command
     for pair in commit {
-        if let Rule::target = pair.as_rule() {
-            target = parse_target(pair);
+        match pair.as_rule() {
+            // Written between the branches, it is rendered above the commit
+            Rule::comment => comments.push(parse_comment(pair)),
+            Rule::target => targets.extend(parse_target(pair)),
+            Rule::deletion => deletions.extend(parse_target(pair)),
+            Rule::tag => tag = parse_tag(pair),
+            Rule::exec => {
+                test = pair
+                    .into_inner()
+                    .next()
+                    .map(|command| command.as_str().trim().to_string())
+                    .filter(|command| !command.is_empty())
+            }
+            _ => (),
         }
     }
 
     Some(Commit {
         hash,
         title: title.to_string(),
-        target,
+        targets,
+        tag,
+        comments,
+        deletions,
+        test,
     })
 }
 
@@ -120,40 +478,713 @@ fn parse_value(pair: Pair<Rule>) -> Option<Vec<Commit>> {
     }
 }
 
-pub fn instruction_from_string(input: String) -> Option<Vec<Commit>> {
-    let pair = YggitParser::parse(Rule::commits, &input)
-        .map_err(|err| println!("{err}"))
-        .ok()?
-        .next()?;
-    let commits = parse_value(pair)?;
+/// A branch line of the todo that is not under a commit line
+///
+/// It happens when the line of its commit has been deleted
+#[derive(Debug)]
+pub struct OrphanBranchLine {
+    pub branch: String,
+}
+
+impl std::fmt::Display for OrphanBranchLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the branch {} is not under a commit, has the line of its commit been deleted?",
+            self.branch
+        )
+    }
+}
+
+impl std::error::Error for OrphanBranchLine {}
+
+/// Check that no branch line comes before the first commit line
+///
+/// The lines under a commit are all its branches, so only the first commit line can be missing
+fn check_orphan_branch_lines(input: &str) -> Result<(), OrphanBranchLine> {
+    for line in input.lines() {
+        let line = line.trim();
+        let is_commit = line.len() >= 40
+            && line.is_char_boundary(40)
+            && line[..40].chars().all(|char| char.is_ascii_hexdigit())
+            && line[40..].chars().next().map_or(true, |char| char == ' ');
+        if is_commit {
+            return Ok(());
+        }
+        let Some(target) = line.strip_prefix("->").map(str::trim) else {
+            continue;
+        };
+        if !target.starts_with("tag:") {
+            return Err(OrphanBranchLine {
+                branch: target.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The todo does not follow the grammar
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub col: usize,
+    /// Text of the offending line
+    pub snippet: String,
+    /// What was expected instead
+    pub expected: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "parse error at line {}, column {}: expected {}\n  {}",
+            self.line, self.col, self.expected, self.snippet
+        )
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl From<pest::error::Error<Rule>> for SyntaxError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        let (line, col) = match error.line_col {
+            pest::error::LineColLocation::Pos(position) => position,
+            pest::error::LineColLocation::Span(start, _) => start,
+        };
+        let expected = match &error.variant {
+            pest::error::ErrorVariant::ParsingError { positives, .. } if !positives.is_empty() => {
+                positives
+                    .iter()
+                    .map(rule_name)
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            }
+            _ => "a commit, a branch or a comment".to_string(),
+        };
+        SyntaxError {
+            line,
+            col,
+            snippet: error.line().to_string(),
+            expected,
+        }
+    }
+}
+
+/// Human name of a rule, for the parse errors
+fn rule_name(rule: &Rule) -> String {
+    match rule {
+        Rule::commit_hash | Rule::git_commit | Rule::commit | Rule::commits => "a commit",
+        Rule::commit_title => "a title",
+        Rule::origin | Rule::url => "an origin",
+        Rule::branch_name => "a branch name",
+        Rule::target => "a branch",
+        Rule::tag_name => "a tag name",
+        Rule::tag_message => "a tag message",
+        Rule::tag => "a tag",
+        Rule::comment => "a comment",
+        Rule::EOI => "the end of the file",
+        _ => "a valid line",
+    }
+    .to_string()
+}
 
-    Some(commits)
+pub fn instruction_from_string(input: String) -> anyhow::Result<Vec<Commit>> {
+    check_orphan_branch_lines(&input)?;
+    let pair = YggitParser::parse(Rule::todo, &input)
+        .map_err(SyntaxError::from)?
+        .next()
+        .context("there is no commit")?;
+    parse_value(pair).context("the commits are malformed")
 }
 
 #[cfg(test)]
 mod test {
+    use git2::Oid;
     use pest::Parser;
 
-    use super::{Rule, YggitParser};
+    use super::{
+        commits_to_colored_string, commits_to_formatted_string, commits_to_graph, commits_to_json,
+        commits_to_string, instruction_from_string, Filter, OrphanBranchLine, Rule, SyntaxError,
+        YggitParser,
+    };
+    use crate::{
+        core::{Note, Push, PushStrategy, Tag, TEST_KEY},
+        git::EnhancedCommit,
+    };
+
+    fn stack() -> Vec<EnhancedCommit<Note>> {
+        let commit = |id: &str, title: &str, push: Option<Push>| EnhancedCommit {
+            id: Oid::from_str(id).unwrap(),
+            parents: Vec::default(),
+            title: title.to_string(),
+            description: None,
+            note: Some(Note {
+                pushes: push.into_iter().collect(),
+                ..Default::default()
+            }),
+        };
+        vec![
+            commit(
+                "01",
+                "first",
+                Some(Push {
+                    origin: None,
+                    branch: "feature".into(),
+                    strategy: None,
+                }),
+            ),
+            commit("02", "second", None),
+            commit(
+                "03",
+                "third",
+                Some(Push {
+                    origin: Some("upstream".into()),
+                    branch: "other".into(),
+                    strategy: None,
+                }),
+            ),
+        ]
+    }
+
+    fn stack_with_test() -> Vec<EnhancedCommit<Note>> {
+        let mut commits = stack();
+        commits[1]
+            .note
+            .as_mut()
+            .unwrap()
+            .meta
+            .insert(TEST_KEY.to_string(), "cargo test".into());
+        commits
+    }
 
     #[test]
     fn test_hash() {
         let input = "f8fa32837b2f1438a3a55a9341002920ace7978c";
-        let result = YggitParser::parse(Rule::commit_hash, &input).expect("should be parsed");
+        let result = YggitParser::parse(Rule::commit_hash, input).expect("should be parsed");
         assert_eq!(result.as_str(), input)
     }
 
     #[test]
     fn test_commit_title() {
         let input = "project: add .vscode in gitignore";
-        let result = YggitParser::parse(Rule::commit_title, &input).expect("should be parsed");
+        let result = YggitParser::parse(Rule::commit_title, input).expect("should be parsed");
         assert_eq!(result.as_str(), input)
     }
 
     #[test]
     fn test_git_commit() {
         let input = "f8fa32837b2f1438a3a55a9341002920ace7978c project: add .vscode in gitignore\n";
-        let result = YggitParser::parse(Rule::git_commit, &input).expect("should be parsed");
+        let result = YggitParser::parse(Rule::git_commit, input).expect("should be parsed");
         assert_eq!(result.as_str(), input)
     }
+
+    #[test]
+    fn test_empty_title_roundtrip() {
+        let mut commits = stack();
+        commits[1].title = String::default();
+        let output = commits_to_string(commits);
+        let id = Oid::from_str("02").unwrap();
+        assert!(output.contains(&format!("\n{}\n", id)));
+
+        let parsed = instruction_from_string(output.clone()).expect("should be parsed");
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[1].hash, id);
+        assert_eq!(parsed[1].title, "");
+
+        // A trailing space after the id is also accepted
+        let input = format!("{} \n", id);
+        let parsed = instruction_from_string(input).expect("should be parsed");
+        assert_eq!(parsed[0].title, "");
+
+        let mut commits = stack();
+        commits[1].title = String::default();
+        assert_eq!(commits_to_string(commits), output);
+    }
+
+    #[test]
+    fn test_comment_between_targets() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "# the branch of the review\n",
+            "-> feature\n",
+            "# the branch of the demo\n",
+            "-> demo\n",
+            "\n",
+            "# waiting for the review of feature\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            "\n",
+            "# Here is how to use yggit\n",
+        );
+        let parsed = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(parsed.len(), 2);
+        let branches: Vec<&str> = parsed[0]
+            .targets
+            .iter()
+            .map(|target| target.branch.as_str())
+            .collect();
+        assert_eq!(branches, vec!["feature", "demo"]);
+        assert_eq!(
+            parsed[0].comments,
+            vec!["the branch of the review", "the branch of the demo"]
+        );
+        assert_eq!(
+            parsed[1].comments,
+            vec!["waiting for the review of feature"]
+        );
+    }
+
+    #[test]
+    fn test_comments_roundtrip() {
+        let input = concat!(
+            "# the whole stack is about the login\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "\n",
+            "# waiting for the review of feature\n",
+            "#\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            "\n",
+            "# Here is how to use yggit\n",
+        );
+        let parsed = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(
+            parsed[0].comments,
+            vec!["the whole stack is about the login"]
+        );
+        assert_eq!(
+            parsed[1].comments,
+            vec!["waiting for the review of feature", ""]
+        );
+
+        let commits = parsed
+            .into_iter()
+            .map(|commit| EnhancedCommit {
+                id: commit.hash,
+                parents: Vec::default(),
+                title: commit.title,
+                description: None,
+                note: Some(Note {
+                    pushes: commit
+                        .targets
+                        .into_iter()
+                        .map(|target| Push {
+                            origin: target.origin,
+                            branch: target.branch,
+                            strategy: None,
+                        })
+                        .collect(),
+                    comments: commit.comments,
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let output = commits_to_string(commits);
+        assert_eq!(
+            output,
+            concat!(
+                "# the whole stack is about the login\n",
+                "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+                "-> feature\n",
+                "\n",
+                "# waiting for the review of feature\n",
+                "#\n",
+                "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_syntax_error_position() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "\n",
+            "f8fa328 second commit\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978e third commit\n",
+        );
+        let error = instruction_from_string(input.to_string()).expect_err("should fail");
+        let Some(error) = error.downcast_ref::<SyntaxError>() else {
+            panic!("should be a syntax error")
+        };
+        assert_eq!((error.line, error.col), (4, 1));
+        assert_eq!(error.snippet, "f8fa328 second commit");
+        assert!(error
+            .to_string()
+            .starts_with("parse error at line 4, column 1"));
+    }
+
+    #[test]
+    fn test_url_origin() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> file:///tmp/repo.git:feature\n",
+            "\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+            "-> git@github.com:Pilou97/yggit.git:other\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        let target = commits[0]
+            .targets
+            .first()
+            .expect("branch should be present");
+        assert_eq!(
+            target.origin.as_ref().map(|origin| origin.as_str()),
+            Some("file:///tmp/repo.git")
+        );
+        assert_eq!(target.branch.as_str(), "feature");
+        let target = commits[1]
+            .targets
+            .first()
+            .expect("branch should be present");
+        assert_eq!(
+            target.origin.as_ref().map(|origin| origin.as_str()),
+            Some("git@github.com:Pilou97/yggit.git")
+        );
+        assert_eq!(target.branch.as_str(), "other");
+    }
+
+    #[test]
+    fn test_test_command() {
+        let output = commits_to_colored_string(stack_with_test(), Filter::All, true, false);
+        let id = Oid::from_str("02").unwrap();
+        assert!(output.contains(&format!("{} second\n# $ cargo test\n\n", id)));
+
+        let output = commits_to_colored_string(stack_with_test(), Filter::All, false, false);
+        assert!(!output.contains("# $"));
+        let output = commits_to_colored_string(stack_with_test(), Filter::CommitsOnly, true, false);
+        assert!(!output.contains("# $"));
+        // The editor does not show it
+        assert!(!commits_to_string(stack_with_test()).contains("# $"));
+    }
+
+    #[test]
+    fn test_multi_line_title() {
+        let mut commits = stack();
+        commits[1].title = "second\nline\r".to_string();
+        let output = commits_to_string(commits);
+        let id = Oid::from_str("02").unwrap();
+        assert!(output.contains(&format!("\n{} second line \n", id)));
+
+        let parsed = instruction_from_string(output).expect("should be parsed");
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[1].title, "second line ");
+        assert_eq!(parsed[2].hash, Oid::from_str("03").unwrap());
+    }
+
+    #[test]
+    fn test_format() {
+        let output = commits_to_formatted_string(stack(), "%h %b");
+        assert_eq!(output, "0100000 feature\n0200000 \n0300000 other\n");
+
+        let output = commits_to_formatted_string(stack(), "%o/%b: %s (%x) 100%");
+        assert_eq!(
+            output,
+            concat!(
+                "/feature: first (%x) 100%\n",
+                "/: second (%x) 100%\n",
+                "upstream/other: third (%x) 100%\n",
+            )
+        );
+
+        let output = commits_to_formatted_string(stack(), "%H");
+        assert!(output.starts_with(&format!("{}\n", Oid::from_str("01").unwrap())));
+    }
+
+    #[test]
+    fn test_json() {
+        let stack = commits_to_json(stack(), Some("main".to_string()));
+        let json = serde_json::to_value(stack).expect("should be serialized");
+        let oid = |id| Oid::from_str(id).unwrap().to_string();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "onto": "main",
+                "entries": [
+                    {"oid": oid("01"), "title": "first", "branch": "feature", "origin": null, "branches": ["feature"], "base": "main"},
+                    {"oid": oid("02"), "title": "second", "branch": null, "origin": null, "branches": [], "base": "feature"},
+                    {"oid": oid("03"), "title": "third", "branch": "other", "origin": "upstream", "branches": ["other"], "base": "feature"},
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_orphan_branch_line() {
+        let input = concat!(
+            "-> feature\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978c second commit\n",
+        );
+        let error = instruction_from_string(input.to_string()).expect_err("should fail");
+        let Some(OrphanBranchLine { branch }) = error.downcast_ref() else {
+            panic!("the branch line should be orphan")
+        };
+        assert_eq!(branch, "feature");
+    }
+
+    #[test]
+    fn test_several_branches_per_commit() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "-> tag:v1\n",
+            "-> upstream:release\n",
+            "\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(commits.len(), 2);
+        let branches: Vec<String> = commits[0]
+            .targets
+            .iter()
+            .map(|target| match &target.origin {
+                Some(origin) => format!("{}:{}", origin, target.branch),
+                None => target.branch.to_string(),
+            })
+            .collect();
+        assert_eq!(branches, vec!["feature", "upstream:release"]);
+        assert!(commits[1].targets.is_empty());
+
+        let mut stack = stack();
+        stack[0].note.as_mut().unwrap().pushes.push(Push {
+            origin: None,
+            branch: "release".into(),
+            strategy: None,
+        });
+        let output = commits_to_string(stack);
+        assert!(output.contains("-> feature\n-> release\n"));
+    }
+
+    #[test]
+    fn test_deletion() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "-> !old-feature\n",
+            "-> !upstream:release\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(commits[0].targets.len(), 1);
+        let deletions: Vec<String> = commits[0]
+            .deletions
+            .iter()
+            .map(|target| format!("{:?}:{}", target.origin, target.branch))
+            .collect();
+        assert_eq!(
+            deletions,
+            vec!["None:old-feature", "Some(Origin(\"upstream\")):release"]
+        );
+    }
+
+    #[test]
+    fn test_exec() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "$ cargo test --all > /dev/null\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d second commit\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(commits[0].targets.len(), 1);
+        assert_eq!(
+            commits[0].test.as_deref(),
+            Some("cargo test --all > /dev/null")
+        );
+        assert_eq!(commits[1].test, None);
+    }
+
+    #[test]
+    fn test_title_with_arrow() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c -> fix the arrow\n",
+            "f8fa32837b2f1438a3a55a9341002920ace7978d $ run, \"the\" tests > /dev/null\n",
+            "-> feature\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].title, "-> fix the arrow");
+        assert!(commits[0].targets.is_empty());
+        assert_eq!(commits[1].title, "$ run, \"the\" tests > /dev/null");
+        let target = commits[1]
+            .targets
+            .first()
+            .expect("branch should be present");
+        assert_eq!(target.branch.as_str(), "feature");
+    }
+
+    #[test]
+    fn test_branches_only() {
+        let output = commits_to_colored_string(stack(), Filter::BranchesOnly, true, false);
+        assert_eq!(output, "feature\nupstream:other\n");
+    }
+
+    #[test]
+    fn test_descriptions() {
+        let mut commits = stack();
+        commits[0].description = Some("Hello there\n\nGeneral Kenobi".to_string());
+        let output = commits_to_colored_string(commits, Filter::CommitsOnly, true, true);
+        let expected = format!(
+            "{} first\n    Hello there\n\n    General Kenobi\n{} second\n{} third\n",
+            Oid::from_str("01").unwrap(),
+            Oid::from_str("02").unwrap(),
+            Oid::from_str("03").unwrap()
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_commits_only() {
+        let output = commits_to_colored_string(stack(), Filter::CommitsOnly, true, false);
+        let expected = format!(
+            "{} first\n{} second\n{} third\n",
+            Oid::from_str("01").unwrap(),
+            Oid::from_str("02").unwrap(),
+            Oid::from_str("03").unwrap()
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_lightweight_tag() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> tag:v1\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        let tag = commits[0].tag.as_ref().expect("tag should be present");
+        assert_eq!(tag.name, "v1");
+        assert_eq!(tag.message, None);
+        assert!(commits[0].targets.is_empty());
+    }
+
+    #[test]
+    fn test_annotated_tag_with_branch() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> feature\n",
+            "-> tag:v1.0 \"release candidate\"\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        let tag = commits[0].tag.as_ref().expect("tag should be present");
+        assert_eq!(tag.name, "v1.0");
+        assert_eq!(tag.message.as_deref(), Some("release candidate"));
+        let target = commits[0]
+            .targets
+            .first()
+            .expect("branch should be present");
+        assert_eq!(target.branch.as_str(), "feature");
+    }
+
+    #[test]
+    fn test_tag_roundtrip() {
+        let mut commits = stack();
+        commits[1].note = Some(Note {
+            pushes: Vec::default(),
+            tag: Some(Tag {
+                name: "v1".to_string(),
+                message: Some("release candidate".to_string()),
+            }),
+            ..Default::default()
+        });
+        let output = commits_to_string(commits);
+        assert!(output.contains("-> tag:v1 \"release candidate\"\n"));
+
+        let commits = instruction_from_string(output).expect("should be parsed");
+        assert_eq!(commits.len(), 3);
+        let tag = commits[1].tag.as_ref().expect("tag should be present");
+        assert_eq!(tag.name, "v1");
+        assert_eq!(tag.message.as_deref(), Some("release candidate"));
+        assert!(commits[1].targets.is_empty());
+    }
+
+    #[test]
+    fn test_push_strategy_roundtrip() {
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "-> origin:shared (normal)\n",
+            "-> feature (force)\n",
+            "-> other\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        let strategies: Vec<Option<PushStrategy>> = commits[0]
+            .targets
+            .iter()
+            .map(|target| target.strategy)
+            .collect();
+        assert_eq!(
+            strategies,
+            vec![Some(PushStrategy::Normal), Some(PushStrategy::Force), None]
+        );
+
+        let mut commits = stack();
+        commits[0].note = Some(Note {
+            pushes: vec![Push {
+                origin: None,
+                branch: "shared".into(),
+                strategy: Some(PushStrategy::Lease),
+            }],
+            ..Default::default()
+        });
+        let output = commits_to_string(commits);
+        assert!(output.contains("-> shared (lease)\n"));
+        let commits = instruction_from_string(output).expect("should be parsed");
+        assert_eq!(commits[0].targets[0].strategy, Some(PushStrategy::Lease));
+    }
+
+    #[test]
+    fn test_exec_roundtrip() {
+        let mut commits = stack();
+        let mut meta = serde_json::Map::default();
+        meta.insert(TEST_KEY.to_string(), "cargo test".into());
+        commits[1].note = Some(Note {
+            pushes: vec![Push {
+                origin: None,
+                branch: "feature".into(),
+                strategy: None,
+            }],
+            meta,
+            ..Default::default()
+        });
+        let output = commits_to_string(commits);
+        assert!(output.contains("-> feature\n$ cargo test\n"));
+
+        let commits = instruction_from_string(output).expect("should be parsed");
+        assert_eq!(commits.len(), 3);
+        assert_eq!(commits[1].targets[0].branch.as_str(), "feature");
+        assert_eq!(commits[1].test.as_deref(), Some("cargo test"));
+        assert_eq!(commits[0].test, None);
+
+        // exec is the marker of git rebase
+        let input = concat!(
+            "f8fa32837b2f1438a3a55a9341002920ace7978c first commit\n",
+            "exec make check\n",
+            "-> feature\n",
+        );
+        let commits = instruction_from_string(input.to_string()).expect("should be parsed");
+        assert_eq!(commits[0].test.as_deref(), Some("make check"));
+        assert_eq!(commits[0].targets.len(), 1);
+    }
+
+    #[test]
+    fn test_graph() {
+        let mut commits = stack();
+        commits[1].note = Some(Note {
+            pushes: Vec::default(),
+            tag: Some(Tag {
+                name: "v1".to_string(),
+                message: None,
+            }),
+            ..Default::default()
+        });
+        let output = commits_to_graph(commits);
+        assert_eq!(
+            output,
+            concat!(
+                "* 0300000 (upstream:other) third\n",
+                "* 0200000 (tag: v1) second\n",
+                "* 0100000 (feature) first\n",
+            )
+        );
+    }
 }